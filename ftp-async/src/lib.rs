@@ -0,0 +1,419 @@
+//! Asynchronous counterpart of `ftp_cli::FtpClient` built on tokio.
+//!
+//! Commands of every operation and replies expected to them come from `ftp_cli::commands`, so both
+//! clients talk to servers the same way. Connections are opened by [`transport::AsyncTransport`],
+//! plain TCP by default. Data of transfers is available as [`DataStream`] implementing
+//! `AsyncRead` and `AsyncWrite`, which must be handed back to [`AsyncFtpClient::finish`]
+//! once the transfer is done.
+//!
+//! ```
+//! use ftp_async::AsyncFtpClient;
+//! use tokio::io::AsyncReadExt;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() -> Result<(), ftp_cli::FtpError> {
+//! #   let server = ftp_cli::testing::TestServer::start(&[("README", "Hello\n")]);
+//! #   let server = server.address();
+//!     let mut client = AsyncFtpClient::connect(server, None, None).await?;
+//!     client.login("anonymous", "guest", None).await?;
+//!     let mut stream = client.retrieve("README").await?;
+//!     let mut text = String::new();
+//!     stream.read_to_string(&mut text).await?;
+//!     client.finish(stream).await?;
+//!     client.quit().await;
+//!     assert_eq!(text, "Hello\n");
+//!     Ok(())
+//! }
+//! ```
+
+use std::io;
+use std::net::SocketAddrV4;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+
+use ftp_cli::commands::*;
+use ftp_cli::listing::{parse_list, parse_mlsd};
+use ftp_cli::{Encoding, FtpError, FtpMode, RemoteEntry, Trace};
+
+pub mod transport;
+
+use crate::transport::{with_timeout, AsyncChannel, AsyncTransport, TokioTransport};
+
+pub struct AsyncFtpClient {
+    cmd_stream: BufReader<Box<dyn AsyncChannel>>,
+    transport: Arc<dyn AsyncTransport>,
+    mode: FtpMode,
+    encoding: Encoding,
+    trace: Option<Trace>,
+}
+
+/// Data connection of running transfer.
+pub struct DataStream {
+    stream: Box<dyn AsyncChannel>,
+}
+
+impl AsyncFtpClient {
+
+    /// Connects to FTP server and constructs a new `AsyncFtpClient`. With `timeout` the client gives up
+    /// when connection or any later command takes longer. Reads and writes of `DataStream` aren't limited.
+    /// Dialogue with the server is written to `trace`.
+    pub async fn connect(server: &str, timeout: Option<Duration>, trace: Option<Trace>) -> Result<AsyncFtpClient, FtpError> {
+        AsyncFtpClient::connect_transport(server, Arc::new(TokioTransport::new(timeout)), trace).await
+    }
+
+    /// Connects to FTP server over channels opened by `transport`. Commands are limited by timeout
+    /// of the transport.
+    pub async fn connect_transport(server: &str, transport: Arc<dyn AsyncTransport>, trace: Option<Trace>) -> Result<AsyncFtpClient, FtpError> {
+        if let Some(ref trace) = trace {
+            trace.event(&format!("Connecting to {}", server));
+        }
+        let stream = transport.connect(server).await?;
+        if let Some(ref trace) = trace {
+            match stream.peer_addr() {
+                Some(addr) => trace.event(&format!("Connected to {}", addr)),
+                None => trace.event(&format!("Connected to {}", server))
+            }
+        }
+        let mut client = AsyncFtpClient {
+            cmd_stream: BufReader::new(stream),
+            transport,
+            mode: FtpMode::Passive,
+            encoding: Encoding::Utf8,
+            trace,
+        };
+        // Server should welcome the client.
+        expect(client.read_response().await, status::READY_FOR_NEW_USER)?;
+        Ok(client)
+    }
+
+    /// Set FTP transfer mode (Active or Passive)
+    pub fn set_mode(&mut self, mode: FtpMode) {
+        self.mode = mode;
+    }
+
+    /// Current FTP transfer mode.
+    pub fn mode(&self) -> FtpMode {
+        self.mode
+    }
+
+    /// Set encoding of file names and server replies.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Current encoding of file names and server replies.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Network timeout of the transport.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.transport.timeout()
+    }
+
+    /// Transport opening connections of this client.
+    pub fn transport(&self) -> Arc<dyn AsyncTransport> {
+        self.transport.clone()
+    }
+
+    /// Try to authenticate user on server.
+    pub async fn login(&mut self, user: &str, password: &str, account: Option<&str>) -> Result<bool, FtpError> {
+        self.run(login(user, password, account)).await
+    }
+
+    /// Change remote directory.
+    pub async fn cd(&mut self, path: &str) -> Result<(), FtpError> {
+        self.run(change_dir(path)).await
+    }
+
+    /// Delete file on server
+    pub async fn delete(&mut self, path: &str) -> Result<(), FtpError> {
+        self.run(delete(path)).await
+    }
+
+    /// Make directory on server
+    pub async fn mkdir(&mut self, path: &str) -> Result<(), FtpError> {
+        self.run(make_dir(path)).await
+    }
+
+    /// Remove directory
+    pub async fn rmdir(&mut self, path: &str) -> Result<(), FtpError> {
+        self.run(remove_dir(path)).await
+    }
+
+    /// Get current working directory on server.
+    pub async fn pwd(&mut self) -> Result<String, FtpError> {
+        self.run(current_dir()).await
+    }
+
+    /// Get size of remote file in bytes.
+    pub async fn size(&mut self, path: &str) -> Result<u64, FtpError> {
+        self.run(size(path)).await
+    }
+
+    /// Get modification time of remote file in seconds since Unix epoch.
+    pub async fn modified(&mut self, path: &str) -> Result<i64, FtpError> {
+        self.run(modified(path)).await
+    }
+
+    /// List remote directory.
+    pub async fn list(&mut self, path: &str) -> Result<String, FtpError> {
+        self.read_listing(FtpCommand::LIST(path)).await
+    }
+
+    /// List remote directory as structured entries.
+    /// Uses MLSD when server supports it, otherwise parses Unix style LIST output.
+    pub async fn list_entries(&mut self, path: &str) -> Result<Vec<RemoteEntry>, FtpError> {
+        match self.read_listing(FtpCommand::MLSD(path)).await {
+            Ok(text) => Ok(parse_mlsd(&text)),
+            Err(FtpError::UnexpectedReturnCode(status::COMMAND_NOT_RECOGNIZED, _)) |
+            Err(FtpError::UnexpectedReturnCode(status::COMMAND_NOT_IMPLEMENTED, _)) => {
+                let text = self.list(path).await?;
+                Ok(parse_list(&text))
+            }
+            Err(err) => Err(err)
+        }
+    }
+
+    async fn read_listing(&mut self, cmd: FtpCommand<'_>) -> Result<String, FtpError> {
+        self.run(transfer_type(FtpTransferType::Text)).await?;
+        let mut stream = self.init_data_connection(cmd).await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        let text = self.encoding.decode(buf)?;
+        self.finish(stream).await?;
+        Ok(text)
+    }
+
+    /// Start download of remote file. Its content is read from returned stream.
+    pub async fn retrieve(&mut self, remote_path: &str) -> Result<DataStream, FtpError> {
+        self.run(transfer_type(FtpTransferType::Binary)).await?;
+        self.init_data_connection(FtpCommand::RETR(remote_path)).await
+    }
+
+    /// Start upload to remote file. Its content is written to returned stream.
+    pub async fn store(&mut self, remote_path: &str) -> Result<DataStream, FtpError> {
+        self.run(transfer_type(FtpTransferType::Binary)).await?;
+        self.init_data_connection(FtpCommand::STOR(remote_path)).await
+    }
+
+    /// Close data connection of transfer started by `retrieve` or `store` and wait until server confirms it.
+    pub async fn finish(&mut self, mut stream: DataStream) -> Result<(), FtpError> {
+        // Server takes end of data connection as end of uploaded file.
+        stream.stream.shutdown().await?;
+        drop(stream);
+        self.trace_event("Data transfer finished");
+        expect(self.read_response().await, status::CLOSING_DATA_CONNECTION).map(|_| ())
+    }
+
+    /// Download remote file to local path.
+    pub async fn get(&mut self, remote_path: &str, local_path: &str) -> Result<(), FtpError> {
+        let mut stream = self.retrieve(remote_path).await?;
+        let mut file = File::create(local_path).await?;
+        tokio::io::copy(&mut stream, &mut file).await?;
+        file.flush().await?;
+        self.finish(stream).await
+    }
+
+    /// Upload local file to server.
+    pub async fn put(&mut self, local_path: &str, remote_path: &str) -> Result<(), FtpError> {
+        let mut file = File::open(local_path).await?;
+        let mut stream = self.store(remote_path).await?;
+        tokio::io::copy(&mut file, &mut stream).await?;
+        self.finish(stream).await
+    }
+
+    /// Send QUIT command to server and close connection (dropping AsyncFtpClient).
+    pub async fn quit(mut self) {
+        // ignore any error here
+        let _ = self.write_command(FtpCommand::QUIT).await;
+    }
+
+    /// Open data connection for already prepared transfer command.
+    async fn init_data_connection(&mut self, command: FtpCommand<'_>) -> Result<DataStream, FtpError> {
+        match self.mode {
+            FtpMode::Active(addr) => self.init_data_transfer_active(command, addr).await,
+            FtpMode::Passive => self.init_data_transfer_passive(command).await
+        }
+    }
+
+    async fn init_data_transfer_active(&mut self, command: FtpCommand<'_>, addr: SocketAddrV4) -> Result<DataStream, FtpError> {
+        let mut listener = self.transport.listen(addr).await?;
+        // Port 0 lets the system pick a free port, server must be told the actual one.
+        let addr = listener.local_addr()?;
+        self.trace_event(&format!("Listening for data connection on {}", addr));
+        self.run(port(addr)).await?;
+        self.run(open_data(command)).await?;
+        let stream = listener.accept().await?;
+        self.trace_event("Data connection accepted");
+        Ok(DataStream { stream })
+    }
+
+    async fn init_data_transfer_passive(&mut self, command: FtpCommand<'_>) -> Result<DataStream, FtpError> {
+        let addr = self.run(passive()).await?;
+        // Reply to the command comes only once the data connection is open
+        self.write_command(command).await?;
+        self.trace_event(&format!("Opening data connection to {}", addr));
+        let stream = self.transport.connect_data(addr).await?;
+        expect(self.read_response().await, status::OPEN_DATA_CONNECTION)?;
+        Ok(DataStream { stream })
+    }
+
+    /// Send commands of `sequence` and pass it replies until it's done.
+    async fn run<'a, S: Sequence<'a>>(&mut self, mut sequence: S) -> Result<S::Output, FtpError> {
+        self.write_command(sequence.start()).await?;
+        loop {
+            let reply = self.read_response().await;
+            match sequence.next(reply) {
+                Step::Send(command) => self.write_command(command).await?,
+                Step::Read => { }
+                Step::Done(result) => return result
+            }
+        }
+    }
+
+    /// Read response code and text (rest of a line)
+    async fn read_response(&mut self) -> Result<(i32, String), FtpError> {
+        let mut buf = Vec::new();
+        with_timeout(self.transport.timeout(), self.cmd_stream.read_until(b'\n', &mut buf)).await?;
+        let line = self.encoding.decode(buf)?;
+        if let Some(ref trace) = self.trace {
+            trace.reply(&line);
+        }
+        parse_reply(&line)
+    }
+
+    async fn write_command(&mut self, cmd: FtpCommand<'_>) -> io::Result<()> {
+        let text = cmd.to_string();
+        if let Some(ref trace) = self.trace {
+            trace.command(text.trim_end());
+        }
+        let line = self.encoding.encode(&text);
+        let timeout = self.transport.timeout();
+        let stream = self.cmd_stream.get_mut();
+        with_timeout(timeout, async {
+            stream.write_all(&line).await?;
+            stream.flush().await
+        }).await
+    }
+
+    fn trace_event(&self, text: &str) {
+        if let Some(ref trace) = self.trace {
+            trace.event(text);
+        }
+    }
+}
+
+impl AsyncRead for DataStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for DataStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ftp_cli::testing::TestServer;
+
+    use super::*;
+    use crate::transport::{AsyncDataListener, Opening};
+
+    #[tokio::test]
+    async fn runs_session_in_both_modes() {
+        let server = TestServer::start(&[("/pub/a.txt", "hello")]);
+        for &mode in [FtpMode::Passive, FtpMode::Active("127.0.0.1:0".parse().unwrap())].iter() {
+            let mut client = AsyncFtpClient::connect(server.address(), Some(Duration::from_secs(5)), None).await.unwrap();
+            client.set_mode(mode);
+            assert!(client.login("user", "secret", None).await.unwrap());
+            client.cd("/pub").await.unwrap();
+            assert_eq!(client.pwd().await.unwrap(), "/pub");
+            assert_eq!(client.size("a.txt").await.unwrap(), 5);
+
+            let entries = client.list_entries(".").await.unwrap();
+            let names: Vec<_> = entries.iter().map(|entry| (entry.name.as_str(), entry.kind, entry.size)).collect();
+            assert_eq!(names, vec![("a.txt", ftp_cli::EntryKind::File, Some(5))]);
+
+            let mut stream = client.retrieve("a.txt").await.unwrap();
+            let mut text = String::new();
+            stream.read_to_string(&mut text).await.unwrap();
+            client.finish(stream).await.unwrap();
+            assert_eq!(text, "hello");
+
+            let mut stream = client.store("b.txt").await.unwrap();
+            stream.write_all(b"uploaded").await.unwrap();
+            client.finish(stream).await.unwrap();
+            assert_eq!(server.file("/pub/b.txt").unwrap(), b"uploaded");
+
+            client.mkdir("sub").await.unwrap();
+            client.rmdir("sub").await.unwrap();
+            client.delete("b.txt").await.unwrap();
+            assert!(client.delete("b.txt").await.is_err());
+            client.quit().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_wrong_password() {
+        let server = TestServer::start(&[]);
+        let mut client = AsyncFtpClient::connect(server.address(), Some(Duration::from_secs(5)), None).await.unwrap();
+        assert!(!client.login("user", "wrong", None).await.unwrap());
+    }
+
+    /// Opens channels by TCP and counts them.
+    struct Counting(TokioTransport, AtomicUsize);
+
+    impl AsyncTransport for Counting {
+        fn connect<'a>(&'a self, server: &'a str) -> Opening<'a, Box<dyn AsyncChannel>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.connect(server)
+        }
+
+        fn connect_data(&self, addr: SocketAddrV4) -> Opening<'_, Box<dyn AsyncChannel>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.connect_data(addr)
+        }
+
+        fn listen(&self, addr: SocketAddrV4) -> Opening<'_, Box<dyn AsyncDataListener>> {
+            self.0.listen(addr)
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_channels_by_transport() {
+        let server = TestServer::start(&[("README", "read me")]);
+        let transport = Arc::new(Counting(TokioTransport::default(), AtomicUsize::new(0)));
+        let mut client = AsyncFtpClient::connect_transport(server.address(), transport.clone(), None).await.unwrap();
+        assert!(client.login("anonymous", "guest", None).await.unwrap());
+        assert_eq!(client.timeout(), None);
+
+        let local = std::env::temp_dir().join(format!("ftp-async-transport-{}", std::process::id()));
+        client.get("README", local.to_str().unwrap()).await.unwrap();
+        let content = std::fs::read(&local);
+        std::fs::remove_file(&local).unwrap();
+        client.quit().await;
+
+        assert_eq!(content.unwrap(), b"read me");
+        assert_eq!(transport.1.load(Ordering::SeqCst), 2);
+    }
+}
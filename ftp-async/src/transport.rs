@@ -0,0 +1,144 @@
+//! Connections used by `AsyncFtpClient` for control and data channels, counterpart of
+//! `ftp_cli::transport`.
+//!
+//! `TokioTransport` connects directly to the server over tokio TCP streams. Other transports can
+//! carry the channels over TLS, a proxy or in-memory pipes.
+
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+
+/// Bidirectional byte stream carrying control or data channel.
+pub trait AsyncChannel: AsyncRead + AsyncWrite + Send + Unpin {
+
+    /// Address of the other end when the channel is a network connection.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl AsyncChannel for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+/// Channel being opened.
+pub type Opening<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Opens channels to the server.
+pub trait AsyncTransport: Send + Sync {
+
+    /// Open control channel to `server` given as `host:port`.
+    fn connect<'a>(&'a self, server: &'a str) -> Opening<'a, Box<dyn AsyncChannel>>;
+
+    /// Open data channel to address given by server in reply to PASV.
+    fn connect_data(&self, addr: SocketAddrV4) -> Opening<'_, Box<dyn AsyncChannel>>;
+
+    /// Start listening for data channel opened by server in active mode.
+    fn listen(&self, _addr: SocketAddrV4) -> Opening<'_, Box<dyn AsyncDataListener>> {
+        Box::pin(async { Err(io::Error::new(io::ErrorKind::Unsupported, "Active mode is not supported by transport")) })
+    }
+
+    /// Timeout of network operations, if the transport has any.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Debug for dyn AsyncTransport {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "AsyncTransport")
+    }
+}
+
+/// Waits for data channel in active mode.
+pub trait AsyncDataListener: Send {
+    /// Address sent to server in PORT command.
+    fn local_addr(&self) -> io::Result<SocketAddrV4>;
+
+    fn accept(&mut self) -> Opening<'_, Box<dyn AsyncChannel>>;
+}
+
+
+/// Plain TCP connections. With `timeout` opening of connections gives up when it takes longer,
+/// the client applies it to commands as well.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TokioTransport {
+    pub timeout: Option<Duration>,
+}
+
+impl TokioTransport {
+
+    pub fn new(timeout: Option<Duration>) -> TokioTransport {
+        TokioTransport { timeout }
+    }
+}
+
+impl AsyncTransport for TokioTransport {
+
+    fn connect<'a>(&'a self, server: &'a str) -> Opening<'a, Box<dyn AsyncChannel>> {
+        Box::pin(async move {
+            let stream = with_timeout(self.timeout, TcpStream::connect(server)).await?;
+            Ok(Box::new(stream) as Box<dyn AsyncChannel>)
+        })
+    }
+
+    fn connect_data(&self, addr: SocketAddrV4) -> Opening<'_, Box<dyn AsyncChannel>> {
+        Box::pin(async move {
+            let stream = with_timeout(self.timeout, TcpStream::connect(addr)).await?;
+            Ok(Box::new(stream) as Box<dyn AsyncChannel>)
+        })
+    }
+
+    fn listen(&self, addr: SocketAddrV4) -> Opening<'_, Box<dyn AsyncDataListener>> {
+        Box::pin(async move {
+            let listener = TcpListener::bind(addr).await?;
+            Ok(Box::new(TokioDataListener { listener, timeout: self.timeout }) as Box<dyn AsyncDataListener>)
+        })
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+struct TokioDataListener {
+    listener: TcpListener,
+    timeout: Option<Duration>,
+}
+
+impl AsyncDataListener for TokioDataListener {
+
+    fn local_addr(&self) -> io::Result<SocketAddrV4> {
+        match self.listener.local_addr()? {
+            SocketAddr::V4(addr) => Ok(addr),
+            SocketAddr::V6(addr) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("PORT needs IPv4 address, listening on {}", addr)))
+        }
+    }
+
+    fn accept(&mut self) -> Opening<'_, Box<dyn AsyncChannel>> {
+        Box::pin(async move {
+            let (stream, _) = with_timeout(self.timeout, self.listener.accept()).await?;
+            Ok(Box::new(stream) as Box<dyn AsyncChannel>)
+        })
+    }
+}
+
+/// Run network operation failing with `TimedOut` when it takes longer than `timeout`.
+pub(crate) async fn with_timeout<T, F: Future<Output = io::Result<T>>>(timeout: Option<Duration>, operation: F) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, operation).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "operation timed out"))
+        },
+        None => operation.await
+    }
+}
@@ -1,49 +1,483 @@
-use std::net::{SocketAddrV4};
+//! Formatting of commands sent to server and parsing of its replies, shared by all clients.
+//!
+//! Commands of each operation and replies expected to them are kept in a [`Sequence`], so that
+//! clients only send the commands and pass replies back, whichever way they talk to the server.
 
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use crate::error::FtpError;
+use crate::listing::parse_time;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
 pub enum FtpCommand<'a> {
+    ABOR,
+    ACCT(&'a str),
     CWD(&'a str),
     DELE(&'a str),
     LIST(&'a str),
+    MDTM(&'a str),
     MKD(&'a str),
+    MLSD(&'a str),
     PASS(&'a str),
     PASV,
     PORT(SocketAddrV4),
     PWD,
     QUIT,
+    REST(u64),
     RETR(&'a str),
     RMD(&'a str),
+    SIZE(&'a str),
     STOR(&'a str),
     TYPE(FtpTransferType),
     USER(&'a str),
 }
 
+#[derive(Copy, Clone)]
 pub enum FtpTransferType {
     Text,       // ASCII
     Binary,     // IMAGE
 }
 
-impl<'a> ToString for FtpCommand<'a> {
-    fn to_string(&self) -> String {
+impl<'a> Display for FtpCommand<'a> {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
         match *self {
-            FtpCommand::CWD(ref path) => format!("CWD {}\n", path),
-            FtpCommand::DELE(ref path) => format!("DELE {}\n", path),
-            FtpCommand::LIST(ref path) => format!("LIST {}\n", path),
-            FtpCommand::MKD(ref path) => format!("MKD {}\n", path),
-            FtpCommand::PASS(ref pass) => format!("PASS {}\n", pass),
-            FtpCommand::PASV => format!("PASV\n"),
+            FtpCommand::ABOR => writeln!(f, "ABOR"),
+            FtpCommand::ACCT(ref account) => writeln!(f, "ACCT {}", account),
+            FtpCommand::CWD(ref path) => writeln!(f, "CWD {}", path),
+            FtpCommand::DELE(ref path) => writeln!(f, "DELE {}", path),
+            FtpCommand::LIST(ref path) => writeln!(f, "LIST {}", path),
+            FtpCommand::MDTM(ref path) => writeln!(f, "MDTM {}", path),
+            FtpCommand::MKD(ref path) => writeln!(f, "MKD {}", path),
+            FtpCommand::MLSD(ref path) => writeln!(f, "MLSD {}", path),
+            FtpCommand::PASS(ref pass) => writeln!(f, "PASS {}", pass),
+            FtpCommand::PASV => writeln!(f, "PASV"),
             FtpCommand::PORT(addr) => {
                 let ip = addr.ip().octets();
                 let port = addr.port();
-                format!("PORT {},{},{},{},{},{}\n", ip[0], ip[1], ip[2], ip[3], port/256, port%256)
+                writeln!(f, "PORT {},{},{},{},{},{}", ip[0], ip[1], ip[2], ip[3], port/256, port%256)
             }
-            FtpCommand::PWD => format!("PWD\n"),
-            FtpCommand::QUIT => format!("QUIT\n"),
-            FtpCommand::RETR(ref path) => format!("RETR {}\n", path),
-            FtpCommand::RMD(ref path) => format!("RMD {}\n", path),
-            FtpCommand::STOR(ref path) => format!("STOR {}\n", path),
-            FtpCommand::TYPE(FtpTransferType::Text) => "TYPE A\n".to_string(),
-            FtpCommand::TYPE(FtpTransferType::Binary) => "TYPE I\n".to_string(),
-            FtpCommand::USER(ref user) => format!("USER {}\n", user),
+            FtpCommand::PWD => writeln!(f, "PWD"),
+            FtpCommand::QUIT => writeln!(f, "QUIT"),
+            FtpCommand::REST(offset) => writeln!(f, "REST {}", offset),
+            FtpCommand::RETR(ref path) => writeln!(f, "RETR {}", path),
+            FtpCommand::RMD(ref path) => writeln!(f, "RMD {}", path),
+            FtpCommand::SIZE(ref path) => writeln!(f, "SIZE {}", path),
+            FtpCommand::STOR(ref path) => writeln!(f, "STOR {}", path),
+            FtpCommand::TYPE(FtpTransferType::Text) => writeln!(f, "TYPE A"),
+            FtpCommand::TYPE(FtpTransferType::Binary) => writeln!(f, "TYPE I"),
+            FtpCommand::USER(ref user) => writeln!(f, "USER {}", user),
         }
     }
 }
+
+/// Next step of command sequence.
+pub enum Step<'a, T> {
+    /// Send command and pass its reply to the sequence.
+    Send(FtpCommand<'a>),
+    /// Pass one more reply to the previous command to the sequence.
+    Read,
+    Done(Result<T, FtpError>),
+}
+
+/// Commands of single client operation and replies expected to them. Client sends command
+/// given by `start` and passes every reply to `next` until the sequence is done.
+pub trait Sequence<'a> {
+    type Output;
+
+    fn start(&self) -> FtpCommand<'a>;
+
+    fn next(&mut self, reply: Result<(i32, String), FtpError>) -> Step<'a, Self::Output>;
+}
+
+/// Single command expecting reply `code`, text of the reply is converted by `parse`.
+pub struct Exchange<'a, T> {
+    command: FtpCommand<'a>,
+    code: i32,
+    parse: fn(String) -> Result<T, FtpError>,
+}
+
+impl<'a, T> Sequence<'a> for Exchange<'a, T> {
+    type Output = T;
+
+    fn start(&self) -> FtpCommand<'a> {
+        self.command
+    }
+
+    fn next(&mut self, reply: Result<(i32, String), FtpError>) -> Step<'a, T> {
+        Step::Done(expect(reply, self.code).and_then(self.parse))
+    }
+}
+
+fn exchange<T>(command: FtpCommand<'_>, code: i32, parse: fn(String) -> Result<T, FtpError>) -> Exchange<'_, T> {
+    Exchange { command, code, parse }
+}
+
+fn ignore(_text: String) -> Result<(), FtpError> {
+    Ok(())
+}
+
+pub fn change_dir(path: &str) -> Exchange<'_, ()> {
+    exchange(FtpCommand::CWD(path), status::FILE_ACTION_OK, ignore)
+}
+
+pub fn delete(path: &str) -> Exchange<'_, ()> {
+    exchange(FtpCommand::DELE(path), status::FILE_ACTION_OK, ignore)
+}
+
+pub fn make_dir(path: &str) -> Exchange<'_, ()> {
+    exchange(FtpCommand::MKD(path), status::PATHNAME_CREATED, ignore)
+}
+
+pub fn remove_dir(path: &str) -> Exchange<'_, ()> {
+    exchange(FtpCommand::RMD(path), status::FILE_ACTION_OK, ignore)
+}
+
+pub fn current_dir() -> Exchange<'static, String> {
+    exchange(FtpCommand::PWD, status::PATHNAME_CREATED, |text| parse_quoted_path(&text))
+}
+
+/// Modification time of remote file in seconds since Unix epoch.
+pub fn modified(path: &str) -> Exchange<'_, i64> {
+    exchange(FtpCommand::MDTM(path), status::FILE_STATUS, |text| match parse_time(&text) {
+        Some(time) => Ok(time),
+        None => Err(FtpError::InvalidResponse(text))
+    })
+}
+
+pub fn transfer_type(transfer: FtpTransferType) -> Exchange<'static, ()> {
+    exchange(FtpCommand::TYPE(transfer), status::SUCCESS, ignore)
+}
+
+/// Start next transfer at `offset`.
+pub fn restart(offset: u64) -> Exchange<'static, ()> {
+    exchange(FtpCommand::REST(offset), status::FILE_ACTION_PENDING, ignore)
+}
+
+/// Tell server where to open data connection in active mode.
+pub fn port(addr: SocketAddrV4) -> Exchange<'static, ()> {
+    exchange(FtpCommand::PORT(addr), status::SUCCESS, ignore)
+}
+
+/// Ask server for address of data connection in passive mode.
+pub fn passive() -> Exchange<'static, SocketAddrV4> {
+    exchange(FtpCommand::PASV, status::ENTERING_PASSIVE_MODE, |text| parse_passive(&text))
+}
+
+/// Transfer command sent once the data connection is prepared.
+pub fn open_data(command: FtpCommand<'_>) -> Exchange<'_, ()> {
+    exchange(command, status::OPEN_DATA_CONNECTION, ignore)
+}
+
+/// Size of remote file in bytes. Servers usually refuse SIZE in ASCII mode, so binary type is set first.
+pub fn size(path: &str) -> Size<'_> {
+    Size { path, typed: false }
+}
+
+pub struct Size<'a> {
+    path: &'a str,
+    typed: bool,
+}
+
+impl<'a> Sequence<'a> for Size<'a> {
+    type Output = u64;
+
+    fn start(&self) -> FtpCommand<'a> {
+        FtpCommand::TYPE(FtpTransferType::Binary)
+    }
+
+    fn next(&mut self, reply: Result<(i32, String), FtpError>) -> Step<'a, u64> {
+        if !self.typed {
+            self.typed = true;
+            return match expect(reply, status::SUCCESS) {
+                Ok(_) => Step::Send(FtpCommand::SIZE(self.path)),
+                Err(err) => Step::Done(Err(err))
+            };
+        }
+        Step::Done(expect(reply, status::FILE_STATUS).and_then(|text| match text.parse::<u64>() {
+            Ok(size) => Ok(size),
+            Err(_) => Err(FtpError::InvalidResponse(text))
+        }))
+    }
+}
+
+/// Authenticate user. Gives `false` when server refuses the credentials.
+/// Account is sent only when server asks for it.
+pub fn login<'a>(user: &'a str, password: &'a str, account: Option<&'a str>) -> Login<'a> {
+    Login { user, password: Some(password), account }
+}
+
+pub struct Login<'a> {
+    user: &'a str,
+    password: Option<&'a str>,
+    account: Option<&'a str>,
+}
+
+impl<'a> Sequence<'a> for Login<'a> {
+    type Output = bool;
+
+    fn start(&self) -> FtpCommand<'a> {
+        FtpCommand::USER(self.user)
+    }
+
+    fn next(&mut self, reply: Result<(i32, String), FtpError>) -> Step<'a, bool> {
+        match reply {
+            Ok((status::USERNAME_OK_NEED_PASSWORD,_)) if self.password.is_some() => {
+                Step::Send(FtpCommand::PASS(self.password.take().unwrap_or_default()))
+            }
+            Ok((status::NEED_ACCOUNT,_)) if self.account.is_some() => {
+                Step::Send(FtpCommand::ACCT(self.account.take().unwrap_or_default()))
+            }
+            Ok((status::LOGIN_SUCCESSFUL,_)) | Ok((status::SUPERFLUOUS,_)) => Step::Done(Ok(true)),
+            Ok((status::NOT_LOGGED_IN,_)) | Ok((status::INVALID_USERNAME_OR_PASSWORD,_)) => Step::Done(Ok(false)),
+            other => Step::Done(Err(reply_error(other)))
+        }
+    }
+}
+
+/// Abort running transfer after its data connection was closed by client.
+pub fn abort() -> Abort {
+    Abort { replies: 0 }
+}
+
+pub struct Abort {
+    replies: u32,
+}
+
+impl<'a> Sequence<'a> for Abort {
+    type Output = ();
+
+    fn start(&self) -> FtpCommand<'a> {
+        FtpCommand::ABOR
+    }
+
+    fn next(&mut self, reply: Result<(i32, String), FtpError>) -> Step<'a, ()> {
+        self.replies += 1;
+        // Server replies 426 when the transfer was interrupted or 226 when it finished before ABOR arrived.
+        // In both cases it sends one more reply for ABOR itself.
+        match reply {
+            Ok((status::TRANSFER_ABORTED,_)) | Ok((status::CLOSING_DATA_CONNECTION,_)) if self.replies == 1 => Step::Read,
+            Ok((status::CLOSING_DATA_CONNECTION,_)) | Ok((status::DATA_CONNECTION_OPEN,_)) => Step::Done(Ok(())),
+            other => Step::Done(Err(reply_error(other)))
+        }
+    }
+}
+
+
+/// Text of reply when it has expected `code`, error otherwise.
+pub fn expect(reply: Result<(i32, String), FtpError>, code: i32) -> Result<String, FtpError> {
+    match reply {
+        Ok((reply_code, text)) if reply_code == code => Ok(text),
+        other => Err(reply_error(other))
+    }
+}
+
+/// Split reply line into code and text.
+pub fn parse_reply(line: &str) -> Result<(i32, String), FtpError> {
+    if line.is_empty() {
+        return Err(FtpError::IoError(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by server")));
+    }
+    let pos = match line.find(' ') {
+        Some(pos) => pos,
+        None => return Err(FtpError::InvalidResponse(line.to_string()))
+    };
+
+    let code = match line[0..pos].parse::<i32>() {
+        Ok(code) => code,
+        Err(_) => return Err(FtpError::InvalidResponse(line.to_string()))
+    };
+
+    let text = line[pos+1..].trim().to_string();
+    Ok((code, text))
+}
+
+/// Address of data connection from text of reply to PASV, e.g. `Entering Passive Mode (192,168,1,2,195,80)`.
+pub fn parse_passive(text: &str) -> Result<SocketAddrV4, FtpError> {
+    let numbers = match (text.rfind('('), text.rfind(')')) {
+        (Some(start), Some(end)) if start < end => &text[start+1..end],
+        _ => return Err(FtpError::InvalidResponse(text.to_string()))
+    };
+    let nums: Vec<u8> = match numbers.split(',').map(|x| x.trim().parse::<u8>()).collect() {
+        Ok(nums) => nums,
+        Err(_) => return Err(FtpError::InvalidResponse(text.to_string()))
+    };
+    if nums.len() != 6 {
+        return Err(FtpError::InvalidResponse(text.to_string()));
+    }
+    let ip = Ipv4Addr::new(nums[0], nums[1], nums[2], nums[3]);
+    let port = to_ftp_port(nums[4] as u16, nums[5] as u16);
+    Ok(SocketAddrV4::new(ip, port))
+}
+
+/// Path quoted at start of reply 257, e.g. `"/home/u" is the current directory`.
+/// Quotes inside the path are doubled (RFC 959).
+pub fn parse_quoted_path(text: &str) -> Result<String, FtpError> {
+    let mut chars = match text.find('"') {
+        Some(start) => text[start+1..].chars().peekable(),
+        None => return Err(FtpError::InvalidResponse(text.to_string()))
+    };
+    let mut path = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.peek() == Some(&'"') => {
+                chars.next();
+                path.push('"');
+            }
+            '"' => return Ok(path),
+            c => path.push(c)
+        }
+    }
+    Err(FtpError::InvalidResponse(text.to_string()))
+}
+
+/// Error for reply the client didn't expect.
+pub fn reply_error(result: Result<(i32, String), FtpError>) -> FtpError {
+    match result {
+        Ok((status::OPERATION_FAILED, text)) => FtpError::FileUnavailable(text),
+        Ok((code, text)) => FtpError::UnexpectedReturnCode(code, text),
+        Err(err) => err
+    }
+}
+
+fn to_ftp_port(b1: u16, b2: u16) -> u16 {
+    b1 *256 + b2
+}
+
+/// Reply codes the clients expect.
+pub mod status {
+    pub const OPEN_DATA_CONNECTION : i32 = 150;
+    pub const SUCCESS : i32 = 200;
+    pub const SUPERFLUOUS : i32 = 202;
+    pub const READY_FOR_NEW_USER : i32 = 220;
+    pub const ENTERING_PASSIVE_MODE : i32 = 227;
+    pub const FILE_STATUS : i32 = 213;
+    pub const DATA_CONNECTION_OPEN : i32 = 225;
+    pub const CLOSING_DATA_CONNECTION : i32 = 226;
+    pub const LOGIN_SUCCESSFUL : i32 = 230;
+    pub const FILE_ACTION_OK : i32 = 250;
+    pub const PATHNAME_CREATED : i32 = 257;
+    pub const USERNAME_OK_NEED_PASSWORD : i32 = 331;
+    pub const NEED_ACCOUNT : i32 = 332;
+    pub const FILE_ACTION_PENDING : i32 = 350;
+    pub const TRANSFER_ABORTED : i32 = 426;
+    pub const INVALID_USERNAME_OR_PASSWORD : i32 = 430;
+    pub const COMMAND_NOT_RECOGNIZED : i32 = 500;
+    pub const COMMAND_NOT_IMPLEMENTED : i32 = 502;
+    pub const NOT_LOGGED_IN : i32 = 530;
+    pub const OPERATION_FAILED : i32 = 550;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_path() {
+        let cases = [
+            ("\"/home\"", "/home"),
+            ("\"/home/u\" is the current directory", "/home/u"),
+            ("\"/\" is current directory.", "/"),
+            ("\"/dir \"\"quoted\"\"\" created", "/dir \"quoted\""),
+            ("\"\"", ""),
+            ("Current directory is \"/pub\"", "/pub"),
+        ];
+        for &(text, path) in cases.iter() {
+            assert_eq!(parse_quoted_path(text).unwrap(), path, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_unquoted_path() {
+        for text in ["", "/home", "\"/home", "\"/home\"\""].iter() {
+            assert!(parse_quoted_path(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_reply_line() {
+        assert_eq!(parse_reply("220 Service ready\r\n").unwrap(), (220, "Service ready".to_string()));
+        assert!(parse_reply("garbage\r\n").is_err());
+        match parse_reply("") {
+            Err(FtpError::IoError(ref err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    /// Commands sent by `sequence` given reply lines in order, and its result.
+    fn replay<'a, S: Sequence<'a>>(mut sequence: S, replies: &[&str]) -> (Vec<String>, Option<Result<S::Output, FtpError>>) {
+        let mut sent = vec![sequence.start().to_string().trim_end().to_string()];
+        for reply in replies {
+            match sequence.next(parse_reply(reply)) {
+                Step::Send(command) => sent.push(command.to_string().trim_end().to_string()),
+                Step::Read => { }
+                Step::Done(result) => return (sent, Some(result))
+            }
+        }
+        (sent, None)
+    }
+
+    #[test]
+    fn logs_in() {
+        // Replies, account, commands sent and result
+        type Case = (&'static [&'static str], Option<&'static str>, &'static [&'static str], Option<bool>);
+        let cases: [Case; 7] = [
+            (&["331 Password", "230 Logged in"], None, &["USER u", "PASS p"], Some(true)),
+            (&["230 No password needed"], None, &["USER u"], Some(true)),
+            (&["331 Password", "202 Superfluous"], None, &["USER u", "PASS p"], Some(true)),
+            (&["331 Password", "530 Login incorrect"], None, &["USER u", "PASS p"], Some(false)),
+            (&["331 Password", "332 Account", "230 Logged in"], Some("a"), &["USER u", "PASS p", "ACCT a"], Some(true)),
+            // Account is needed but not given
+            (&["331 Password", "332 Account"], None, &["USER u", "PASS p"], None),
+            // Password is sent only once
+            (&["331 Password", "331 Password"], None, &["USER u", "PASS p"], None),
+        ];
+        for &(replies, account, commands, logged_in) in cases.iter() {
+            let (sent, result) = replay(login("u", "p", account), replies);
+            assert_eq!(sent, commands, "{:?}", replies);
+            assert_eq!(result.unwrap().ok(), logged_in, "{:?}", replies);
+        }
+    }
+
+    #[test]
+    fn checks_size_after_binary_type() {
+        let (sent, result) = replay(size("a b.txt"), &["200 Type set", "213 1024"]);
+        assert_eq!(sent, vec!["TYPE I", "SIZE a b.txt"]);
+        assert_eq!(result.unwrap().unwrap(), 1024);
+
+        let (sent, result) = replay(size("a"), &["504 Not in binary", "213 1024"]);
+        assert_eq!(sent, vec!["TYPE I"]);
+        assert!(result.unwrap().is_err());
+        assert!(replay(size("a"), &["200 Type set", "213 big"]).1.unwrap().is_err());
+        match replay(size("a"), &["200 Type set", "550 No such file"]).1.unwrap() {
+            Err(FtpError::FileUnavailable(text)) => assert_eq!(text, "No such file"),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn aborts_transfer() {
+        let cases: [(&[&str], bool); 5] = [
+            (&["426 Transfer aborted", "226 Abort successful"], true),
+            (&["226 Transfer complete", "226 Abort successful"], true),
+            (&["225 No transfer to abort"], true),
+            (&["426 Transfer aborted", "426 Again"], false),
+            (&["500 Unknown command"], false),
+        ];
+        for &(replies, aborted) in cases.iter() {
+            let (sent, result) = replay(abort(), replies);
+            assert_eq!(sent, vec!["ABOR"]);
+            assert_eq!(result.unwrap().is_ok(), aborted, "{:?}", replies);
+        }
+    }
+
+    #[test]
+    fn parses_passive_address() {
+        let addr = parse_passive("Entering Passive Mode (192,168,1,2,195,80)").unwrap();
+        assert_eq!(addr, "192.168.1.2:50000".parse().unwrap());
+        assert!(parse_passive("Entering Passive Mode (192,168,1,2,195)").is_err());
+        assert!(parse_passive("Entering Passive Mode").is_err());
+    }
+}
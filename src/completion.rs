@@ -0,0 +1,213 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use ftp_cli::error::FtpError;
+use ftp_cli::ftp_client::FtpClient;
+use ftp_cli::listing::{is_safe_name, EntryKind};
+use ftp_cli::pool::SessionConfig;
+use crate::shell::{escape_word, split_words};
+use ftp_cli::tree::join_remote;
+
+/// Commands offered when completing the first word of a line.
+const COMMANDS: &[&str] = &[
+    "cd", "get", "lcd", "lls", "lmkdir", "lpwd", "lrm", "ls", "mget", "mirror", "mkdir",
+    "mput", "profiles", "prompt", "put", "pwd", "q", "quit", "rate", "rm", "rmdir", "sync",
+];
+
+/// Commands taking remote paths as arguments.
+const REMOTE_COMMANDS: &[&str] = &["cd", "get", "ls", "mget", "mkdir", "rm", "rmdir"];
+
+/// Commands taking local paths as arguments.
+const LOCAL_COMMANDS: &[&str] = &["lcd", "lls", "lmkdir", "lrm", "mput", "put"];
+
+
+/// Tab completion of command names, local and remote paths.
+/// Remote directories are listed by separate session so completion never interferes
+/// with the state of the main connection. Listings are cached until `invalidate` is called.
+pub struct ShellHelper {
+    remote: RefCell<RemoteListings>,
+}
+
+struct RemoteListings {
+    config: SessionConfig,
+    session: Option<FtpClient>,
+    cwd: String,
+    /// Entry names and directory flags keyed by absolute directory path.
+    cache: HashMap<String, Vec<(String, bool)>>,
+}
+
+impl ShellHelper {
+
+    /// Creates helper completing remote paths relative to `cwd` of the main session.
+    pub fn new(config: SessionConfig, cwd: &str) -> ShellHelper {
+        ShellHelper {
+            remote: RefCell::new(RemoteListings {
+                config,
+                session: None,
+                cwd: cwd.to_string(),
+                cache: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Update remote working directory after `cd`.
+    pub fn set_remote_dir(&self, cwd: &str) {
+        self.remote.borrow_mut().cwd = cwd.to_string();
+    }
+
+    /// Forget cached listings. Called after commands modifying remote files.
+    pub fn invalidate(&self) {
+        self.remote.borrow_mut().cache.clear();
+    }
+}
+
+impl RemoteListings {
+
+    fn list(&mut self, dir: &str) -> Result<Vec<(String, bool)>, FtpError> {
+        if let Some(entries) = self.cache.get(dir) {
+            return Ok(entries.clone());
+        }
+
+        let mut session = match self.session.take() {
+            Some(session) => session,
+            None => self.config.open()?
+        };
+        let entries: Vec<(String, bool)> = match session.list_entries(dir) {
+            Ok(entries) => entries.into_iter()
+                .filter(|e| is_safe_name(&e.name))
+                .map(|e| (e.name, e.kind == EntryKind::Directory))
+                .collect(),
+            // Connection might have timed out, open new one next time
+            Err(FtpError::IoError(err)) => return Err(FtpError::IoError(err)),
+            Err(err) => {
+                self.session = Some(session);
+                return Err(err);
+            }
+        };
+        self.session = Some(session);
+
+        self.cache.insert(dir.to_string(), entries.clone());
+        Ok(entries)
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> ::rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = word_start(line);
+        // Completed word is unquoted, candidates are inserted escaped
+        let word = match split_words(&line[start..]) {
+            Ok(ref words) if words.len() <= 1 => words.first().cloned().unwrap_or_default(),
+            _ => return Ok((start, Vec::new()))
+        };
+        let word = word.as_str();
+
+        let command = match line.split_whitespace().next() {
+            Some(command) if start > 0 => command,
+            // First word is the command itself
+            _ => {
+                let candidates = COMMANDS.iter()
+                    .filter(|c| c.starts_with(word))
+                    .map(|c| Pair { display: c.to_string(), replacement: format!("{} ", c) })
+                    .collect();
+                return Ok((start, candidates));
+            }
+        };
+
+        if word.starts_with('-') {
+            return Ok((start, Vec::new()));
+        }
+
+        // Directory part is kept as typed, only the last component is completed
+        let (dir, prefix) = match word.rfind('/') {
+            Some(p) => (&word[..p + 1], &word[p + 1..]),
+            None => ("", word)
+        };
+
+        let entries = if REMOTE_COMMANDS.contains(&command) {
+            let mut remote = self.remote.borrow_mut();
+            let absolute = match dir.starts_with('/') {
+                true => dir.to_string(),
+                false => join_remote(&remote.cwd, dir)
+            };
+            remote.list(&absolute).unwrap_or_default()
+        }
+        else if LOCAL_COMMANDS.contains(&command) {
+            list_local(dir)
+        }
+        else {
+            Vec::new()
+        };
+
+        let mut candidates: Vec<Pair> = entries.into_iter()
+            .filter(|(name, _)| name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.')))
+            .map(|(name, is_dir)| {
+                let suffix = if is_dir { "/" } else { "" };
+                Pair {
+                    display: format!("{}{}", name, suffix),
+                    replacement: format!("{}{}", escape_word(&format!("{}{}", dir, name)), suffix),
+                }
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper { }
+
+impl Validator for ShellHelper { }
+
+impl Helper for ShellHelper { }
+
+
+/// Position where the last word of line starts. Whitespace escaped by backslash doesn't end a word.
+fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut escaped = false;
+    for (pos, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        }
+        else if c == '\\' {
+            escaped = true;
+        }
+        else if c.is_whitespace() {
+            start = pos + c.len_utf8();
+        }
+    }
+    start
+}
+
+/// Names in local directory with flags whether they are directories.
+fn list_local(dir: &str) -> Vec<(String, bool)> {
+    let path = match dir {
+        "" => Path::new("."),
+        _ => Path::new(dir)
+    };
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+    entries.filter_map(|e| e.ok())
+        .map(|e| {
+            let is_dir = fs::metadata(e.path()).map(|m| m.is_dir()).unwrap_or(false);
+            (e.file_name().to_string_lossy().into_owned(), is_dir)
+        })
+        .collect()
+}
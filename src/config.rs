@@ -0,0 +1,99 @@
+//! Named connection profiles stored in TOML configuration file.
+//!
+//! ```toml
+//! [profiles.prod-upload]
+//! host = "ftp.example.com"
+//! port = 2121
+//! user = "deploy"
+//! password_command = "pass show ftp/prod"
+//! active = "192.168.1.10:0"
+//! dir = "/incoming"
+//! encoding = "latin1"
+//! timeout = 30
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use ftp_cli::error::FtpError;
+
+
+/// Settings of single server. Values missing in profile use program defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    /// Password stored directly in the file.
+    pub password: Option<String>,
+    /// Name of environment variable holding the password.
+    pub password_env: Option<String>,
+    /// Shell command printing the password.
+    pub password_command: Option<String>,
+    /// Take password from credential vault.
+    #[serde(default)]
+    pub vault: bool,
+    /// Use active mode listening on given address.
+    pub active: Option<String>,
+    /// FTP over TLS.
+    #[serde(default)]
+    pub tls: bool,
+    /// Initial remote directory.
+    pub dir: Option<String>,
+    pub encoding: Option<String>,
+    /// Network timeout in seconds.
+    pub timeout: Option<u64>,
+    pub rate: Option<String>,
+    pub parallel: Option<usize>,
+}
+
+impl Profile {
+
+    /// Password from the file or environment variable.
+    pub fn password(&self) -> Result<Option<String>, FtpError> {
+        match (&self.password, &self.password_env) {
+            (Some(password), _) => Ok(Some(password.clone())),
+            (None, Some(name)) => match ::std::env::var(name) {
+                Ok(password) => Ok(Some(password)),
+                Err(_) => Err(FtpError::OperationFailed(format!("Environment variable {} with password is not set.", name)))
+            },
+            (None, None) => Ok(None)
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+
+    /// Location given by `FTP_CLI_CONFIG` variable or `ftp-cli/config.toml` in user's configuration directory.
+    pub fn path() -> Option<PathBuf> {
+        match ::std::env::var_os("FTP_CLI_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir().map(|dir| dir.join("ftp-cli").join("config.toml"))
+        }
+    }
+
+    /// Read configuration file. Missing file is the same as empty one.
+    pub fn load(path: &Path) -> Result<Config, FtpError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref err) if err.kind() == ::std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(FtpError::IoError(err))
+        };
+        toml::from_str(&text).map_err(|e| FtpError::OperationFailed(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+}
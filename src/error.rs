@@ -4,6 +4,21 @@ use std::convert::From;
 use std::io;
 use std::string::FromUtf8Error;
 
+/// Error of any client operation. Its `category` tells what went wrong regardless of variant.
+///
+/// ```
+/// use ftp_cli::{ErrorCategory, FtpClient};
+///
+/// # let server = ftp_cli::testing::TestServer::start(&[]);
+/// # let server = server.address();
+/// let mut client = FtpClient::connect(server, None, None).unwrap();
+/// # client.login("anonymous", "guest", None).unwrap();
+/// match client.size("missing.txt") {
+///     Ok(size) => println!("{} bytes", size),
+///     Err(ref err) if err.category() == ErrorCategory::NotFound => println!("No such file"),
+///     Err(err) => panic!("{}", err),
+/// }
+/// ```
 #[derive(Debug)]
 pub enum FtpError {
     InvalidResponse(String),
@@ -11,6 +26,123 @@ pub enum FtpError {
     IoError(io::Error),
     EncodingError(FromUtf8Error),
     OperationFailed(String),
+    InvalidArgument(String),
+    /// Server refused action because file is unavailable (reply 550).
+    FileUnavailable(String),
+    LoginFailed(String),
+}
+
+/// Class of error reported to scripts. Each class has its own exit code of the program:
+///
+/// | Code | Category     | Meaning                                               |
+/// |------|--------------|-------------------------------------------------------|
+/// | 1    | `failed`     | Other failure, e.g. some files of bulk transfer failed |
+/// | 2    | `usage`      | Invalid command or arguments                          |
+/// | 3    | `connection` | Server unreachable or connection lost                 |
+/// | 4    | `auth`       | Login refused                                         |
+/// | 5    | `not_found`  | Remote file or directory doesn't exist                |
+/// | 6    | `permission` | Server denied access                                  |
+/// | 7    | `transient`  | Temporary server failure (4xx reply), retry may help  |
+/// | 8    | `permanent`  | Permanent server failure (5xx reply)                  |
+/// | 9    | `local_io`   | Local file can't be read or written                   |
+/// | 10   | `protocol`   | Unexpected or malformed server response               |
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorCategory {
+    Failed,
+    Usage,
+    Connection,
+    Auth,
+    NotFound,
+    Permission,
+    Transient,
+    Permanent,
+    LocalIo,
+    Protocol,
+}
+
+impl ErrorCategory {
+
+    /// Name used in JSON output.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorCategory::Failed => "failed",
+            ErrorCategory::Usage => "usage",
+            ErrorCategory::Connection => "connection",
+            ErrorCategory::Auth => "auth",
+            ErrorCategory::NotFound => "not_found",
+            ErrorCategory::Permission => "permission",
+            ErrorCategory::Transient => "transient",
+            ErrorCategory::Permanent => "permanent",
+            ErrorCategory::LocalIo => "local_io",
+            ErrorCategory::Protocol => "protocol",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ErrorCategory::Failed => 1,
+            ErrorCategory::Usage => 2,
+            ErrorCategory::Connection => 3,
+            ErrorCategory::Auth => 4,
+            ErrorCategory::NotFound => 5,
+            ErrorCategory::Permission => 6,
+            ErrorCategory::Transient => 7,
+            ErrorCategory::Permanent => 8,
+            ErrorCategory::LocalIo => 9,
+            ErrorCategory::Protocol => 10,
+        }
+    }
+}
+
+
+impl FtpError {
+
+    /// Reply code of server response which caused the error.
+    pub fn reply_code(&self) -> Option<i32> {
+        match *self {
+            FtpError::UnexpectedReturnCode(code, _) => Some(code),
+            FtpError::FileUnavailable(_) => Some(550),
+            _ => None
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        match *self {
+            FtpError::InvalidArgument(_) => ErrorCategory::Usage,
+            FtpError::LoginFailed(_) => ErrorCategory::Auth,
+            FtpError::FileUnavailable(ref text) => unavailable_category(text),
+            FtpError::UnexpectedReturnCode(code, ref text) => match code {
+                331 | 332 | 430 | 530 | 532 => ErrorCategory::Auth,
+                421 | 425 | 426 => ErrorCategory::Connection,
+                550 => unavailable_category(text),
+                553 => ErrorCategory::Permission,
+                400..=499 => ErrorCategory::Transient,
+                500..=599 => ErrorCategory::Permanent,
+                _ => ErrorCategory::Protocol
+            },
+            // Network errors have specific kinds (read timeout of socket is WouldBlock on Unix),
+            // anything else comes from local files or terminal
+            FtpError::IoError(ref err) => match err.kind() {
+                io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+                io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::TimedOut |
+                io::ErrorKind::WouldBlock | io::ErrorKind::UnexpectedEof | io::ErrorKind::AddrInUse |
+                io::ErrorKind::AddrNotAvailable | io::ErrorKind::HostUnreachable |
+                io::ErrorKind::NetworkUnreachable | io::ErrorKind::NetworkDown => ErrorCategory::Connection,
+                _ => ErrorCategory::LocalIo
+            },
+            FtpError::InvalidResponse(_) | FtpError::EncodingError(_) => ErrorCategory::Protocol,
+            FtpError::OperationFailed(_) => ErrorCategory::Failed
+        }
+    }
+}
+
+/// Servers use reply 550 both for missing files and denied access, only the text tells them apart.
+fn unavailable_category(text: &str) -> ErrorCategory {
+    let text = text.to_lowercase();
+    match text.contains("permission") || text.contains("denied") || text.contains("not allowed") {
+        true => ErrorCategory::Permission,
+        false => ErrorCategory::NotFound
+    }
 }
 
 impl Error for FtpError {
@@ -21,11 +153,14 @@ impl Error for FtpError {
             FtpError::UnexpectedReturnCode(_,_) => "Received unexpected return code.",
             FtpError::IoError(_) => "Comunication IO error",
             FtpError::EncodingError(_) => "Received text has invalid encoding.",
-            FtpError::OperationFailed(_) => "Operation failed."
+            FtpError::OperationFailed(_) => "Operation failed.",
+            FtpError::InvalidArgument(_) => "Invalid command arguments.",
+            FtpError::FileUnavailable(_) => "File unavailable.",
+            FtpError::LoginFailed(_) => "Login failed."
         }
     }
 
-    fn cause(&self) -> Option<&Error> {
+    fn cause(&self) -> Option<&dyn Error> {
         match *self {
             FtpError::IoError(ref err) => Some(err),
             _ => None
@@ -40,7 +175,26 @@ impl Display for FtpError {
             FtpError::UnexpectedReturnCode(ref code, ref descr) => write!(f, "Received unexpected return code {}. Description \"{}\".", code, descr),
             FtpError::IoError(ref err) => write!(f, "Comunication error: {}.", err),
             FtpError::EncodingError(ref err) => write!(f, "Received text has invalid encoding. Error: \"{}\".", err),
-            FtpError::OperationFailed(ref err) => write!(f, "{}", err)
+            FtpError::OperationFailed(ref err) => write!(f, "{}", err),
+            FtpError::InvalidArgument(ref err) => write!(f, "{}", err),
+            FtpError::FileUnavailable(ref err) => write!(f, "{}", err),
+            FtpError::LoginFailed(ref err) => write!(f, "{}", err)
+        }
+    }
+}
+
+/// IO error can't be cloned, the copy has the same kind and message so it keeps the category.
+impl Clone for FtpError {
+    fn clone(&self) -> Self {
+        match *self {
+            FtpError::InvalidResponse(ref line) => FtpError::InvalidResponse(line.clone()),
+            FtpError::UnexpectedReturnCode(code, ref text) => FtpError::UnexpectedReturnCode(code, text.clone()),
+            FtpError::IoError(ref err) => FtpError::IoError(io::Error::new(err.kind(), err.to_string())),
+            FtpError::EncodingError(ref err) => FtpError::EncodingError(err.clone()),
+            FtpError::OperationFailed(ref text) => FtpError::OperationFailed(text.clone()),
+            FtpError::InvalidArgument(ref text) => FtpError::InvalidArgument(text.clone()),
+            FtpError::FileUnavailable(ref text) => FtpError::FileUnavailable(text.clone()),
+            FtpError::LoginFailed(ref text) => FtpError::LoginFailed(text.clone())
         }
     }
 }
@@ -56,3 +210,68 @@ impl From<FromUtf8Error> for FtpError {
         FtpError::EncodingError(err)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categorizes_io_errors() {
+        let cases = [
+            (io::ErrorKind::ConnectionRefused, ErrorCategory::Connection),
+            (io::ErrorKind::ConnectionReset, ErrorCategory::Connection),
+            (io::ErrorKind::BrokenPipe, ErrorCategory::Connection),
+            (io::ErrorKind::TimedOut, ErrorCategory::Connection),
+            (io::ErrorKind::WouldBlock, ErrorCategory::Connection),
+            (io::ErrorKind::UnexpectedEof, ErrorCategory::Connection),
+            (io::ErrorKind::HostUnreachable, ErrorCategory::Connection),
+            (io::ErrorKind::NotFound, ErrorCategory::LocalIo),
+            (io::ErrorKind::PermissionDenied, ErrorCategory::LocalIo),
+            (io::ErrorKind::IsADirectory, ErrorCategory::LocalIo),
+            (io::ErrorKind::StorageFull, ErrorCategory::LocalIo),
+            (io::ErrorKind::ReadOnlyFilesystem, ErrorCategory::LocalIo),
+            (io::ErrorKind::DirectoryNotEmpty, ErrorCategory::LocalIo),
+            (io::ErrorKind::Other, ErrorCategory::LocalIo),
+        ];
+        for &(kind, category) in cases.iter() {
+            assert_eq!(FtpError::IoError(io::Error::from(kind)).category(), category, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn categorizes_replies() {
+        let cases = [
+            (FtpError::UnexpectedReturnCode(530, "Login incorrect.".to_string()), ErrorCategory::Auth),
+            (FtpError::UnexpectedReturnCode(421, "Timeout.".to_string()), ErrorCategory::Connection),
+            (FtpError::UnexpectedReturnCode(450, "Busy.".to_string()), ErrorCategory::Transient),
+            (FtpError::UnexpectedReturnCode(501, "Syntax error.".to_string()), ErrorCategory::Permanent),
+            (FtpError::UnexpectedReturnCode(553, "Not allowed.".to_string()), ErrorCategory::Permission),
+            (FtpError::UnexpectedReturnCode(120, "Later.".to_string()), ErrorCategory::Protocol),
+            (FtpError::FileUnavailable("No such file or directory.".to_string()), ErrorCategory::NotFound),
+            (FtpError::FileUnavailable("Permission denied.".to_string()), ErrorCategory::Permission),
+            (FtpError::LoginFailed("Invalid username or password.".to_string()), ErrorCategory::Auth),
+            (FtpError::InvalidArgument("Usage.".to_string()), ErrorCategory::Usage),
+            (FtpError::InvalidResponse("garbage".to_string()), ErrorCategory::Protocol),
+            (FtpError::OperationFailed("2 failed.".to_string()), ErrorCategory::Failed),
+        ];
+        for (error, category) in cases.iter() {
+            assert_eq!(error.category(), *category, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn clone_keeps_category_and_message() {
+        let errors = [
+            FtpError::IoError(io::Error::new(io::ErrorKind::ConnectionReset, "reset by peer")),
+            FtpError::IoError(io::Error::from(io::ErrorKind::NotFound)),
+            FtpError::LoginFailed("Invalid username or password.".to_string()),
+            FtpError::UnexpectedReturnCode(450, "Busy.".to_string()),
+        ];
+        for error in errors.iter() {
+            let copy = error.clone();
+            assert_eq!(copy.category(), error.category(), "{:?}", error);
+            assert_eq!(copy.to_string(), error.to_string());
+        }
+    }
+}
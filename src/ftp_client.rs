@@ -1,11 +1,17 @@
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::io::{BufReader, Error as IoError};
-use std::net::{TcpStream, TcpListener, Ipv4Addr, SocketAddrV4};
+use std::net::SocketAddrV4;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use ::commands::*;
-use ::error::*;
-use ::stream::*;
+use crate::commands::*;
+use crate::error::*;
+use crate::listing::*;
+use crate::stream::*;
+use crate::trace::Trace;
+use crate::transport::{Channel, TcpTransport, Transport};
 
 #[derive(Debug, Copy, Clone)]
 pub enum FtpMode {
@@ -13,29 +19,100 @@ pub enum FtpMode {
     Passive
 }
 
+/// Character encoding of file names and server replies.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1 used by older servers.
+    Latin1,
+}
+
+impl Encoding {
+
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None
+        }
+    }
+
+    /// Bytes of command line sent to server.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match *self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            // Characters missing in Latin-1 can't match any file anyway
+            Encoding::Latin1 => text.chars().map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' }).collect()
+        }
+    }
+
+    /// Text of reply or listing received from server.
+    pub fn decode(&self, bytes: Vec<u8>) -> Result<String, FtpError> {
+        match *self {
+            Encoding::Utf8 => Ok(String::from_utf8(bytes)?),
+            Encoding::Latin1 => Ok(bytes.into_iter().map(|b| b as char).collect())
+        }
+    }
+}
+
 pub struct FtpClient {
-    cmd_stream: BufReader<TcpStream>,
+    cmd_stream: BufReader<Box<dyn Channel>>,
+    transport: Arc<dyn Transport>,
     mode: FtpMode,
+    rate_limit: Option<u64>,
+    progress: Option<ProgressHandler>,
+    encoding: Encoding,
+    trace: Option<Trace>,
 }
 
 impl FtpClient {
 
-    /// Connects to FTP server and constructs a new `FtpClient`.
-    pub fn connect(server: &str) -> Result<FtpClient, FtpError> {
-        match TcpStream::connect(server) {
-            Ok(stream) => {
-                let mut client = FtpClient {
-                    cmd_stream: BufReader::new(stream),
-                    mode: FtpMode::Passive,
-                };
-                // Server should welcome the client.
-                match client.read_response() {
-                    Ok((status::READY_FOR_NEW_USER,_)) => Ok(client),
-                    other => Err(to_error(other))
-                }
+    /// Connects to FTP server and constructs a new `FtpClient`. With `timeout` the client gives up
+    /// when connection or any later network operation takes longer. Dialogue with the server
+    /// is written to `trace`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ftp_cli::{FtpClient, Trace};
+    ///
+    /// # let server = ftp_cli::testing::TestServer::start(&[("/incoming/README", "")]);
+    /// # let server = server.address();
+    /// let mut client = FtpClient::connect(server, Some(Duration::from_secs(30)), Some(Trace::stderr())).unwrap();
+    /// client.login("anonymous", "guest", None).unwrap();
+    /// let local = std::env::temp_dir().join("report.csv");
+    /// std::fs::write(&local, "id,value\n1,42\n").unwrap();
+    /// client.put(local.to_str().unwrap(), "incoming/report.csv").unwrap();
+    /// assert_eq!(client.size("incoming/report.csv").unwrap(), 14);
+    /// client.quit();
+    /// ```
+    pub fn connect(server: &str, timeout: Option<Duration>, trace: Option<Trace>) -> Result<FtpClient, FtpError> {
+        FtpClient::connect_transport(server, Arc::new(TcpTransport::new(timeout)), trace)
+    }
+
+    /// Connects to FTP server over channels opened by `transport`.
+    pub fn connect_transport(server: &str, transport: Arc<dyn Transport>, trace: Option<Trace>) -> Result<FtpClient, FtpError> {
+        if let Some(ref trace) = trace {
+            trace.event(&format!("Connecting to {}", server));
+        }
+        let stream = transport.connect(server)?;
+        if let Some(ref trace) = trace {
+            match stream.peer_addr() {
+                Some(addr) => trace.event(&format!("Connected to {}", addr)),
+                None => trace.event(&format!("Connected to {}", server))
             }
-            Err(err) => Err(FtpError::IoError(err))
         }
+        let mut client = FtpClient {
+            cmd_stream: BufReader::new(stream),
+            transport,
+            mode: FtpMode::Passive,
+            rate_limit: None,
+            progress: None,
+            encoding: Encoding::Utf8,
+            trace,
+        };
+        // Server should welcome the client.
+        expect(client.read_response(), status::READY_FOR_NEW_USER)?;
+        Ok(client)
     }
 
     /// Set FTP transfer mode (Active or Passive)
@@ -43,228 +120,396 @@ impl FtpClient {
         self.mode = mode;
     }
 
+    /// Current FTP transfer mode.
+    pub fn mode(&self) -> FtpMode {
+        self.mode
+    }
+
+    /// Limit transfer speed of `get` and `put` to given bytes per second (`None` for unlimited).
+    pub fn set_rate_limit(&mut self, rate: Option<u64>) {
+        self.rate_limit = rate;
+    }
+
+    /// Current transfer speed limit in bytes per second.
+    pub fn rate_limit(&self) -> Option<u64> {
+        self.rate_limit
+    }
+
+    /// Set encoding of file names and server replies.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Current encoding of file names and server replies.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Transport opening connections of this client.
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        self.transport.clone()
+    }
+
+    /// Trace of dialogue with the server.
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Set callback receiving progress of `get` and `put` transfers (`None` to disable reporting).
+    pub fn set_progress_handler(&mut self, handler: Option<ProgressHandler>) {
+        self.progress = handler;
+    }
+
     /// Try to authenticate user on server.
-    pub fn login(&mut self, user: &str, password: &str) -> Result<bool, FtpError> {
-        try!(self.write_command(FtpCommand::USER(user)));
-        match self.read_response() {
-            Ok((status::USERNAME_OK_NEED_PASSWORD,_)) => {
-                try!(self.write_command(FtpCommand::PASS(password)));
-                match self.read_response() {
-                    Ok((status::LOGIN_SUCCESSFUL,_)) => Ok(true),
-                    Ok((status::NOT_LOGGED_IN,_)) | Ok((status::INVALID_USERNAME_OR_PASSWORD,_)) => Ok(false),
-                    other => Err(to_error(other))
-                }
-            }
-            Ok((status::LOGIN_SUCCESSFUL,_)) => Ok(true),
-            Ok((status::NOT_LOGGED_IN,_)) | Ok((status::INVALID_USERNAME_OR_PASSWORD,_)) => Ok(false),
-            other => Err(to_error(other))
-        }
+    pub fn login(&mut self, user: &str, password: &str, account: Option<&str>) -> Result<bool, FtpError> {
+        self.run(login(user, password, account))
     }
 
     /// Change remote directory.
     pub fn cd(&mut self, path: &str) -> Result<(), FtpError> {
-        let cmd = FtpCommand::CWD(path);
-        try!(self.write_command(cmd));
-        match self.read_response() {
-            Ok((status::FILE_ACTION_OK, _)) => Ok(()),
-            other => Err(to_error(other))
-        }
+        self.run(change_dir(path))
     }
 
     /// Delete file on server
     pub fn delete (&mut self, path: &str) -> Result<(), FtpError> {
-        let cmd = FtpCommand::DELE(path);
-        try!(self.write_command(cmd));
-        match self.read_response() {
-            Ok((status::FILE_ACTION_OK, _)) => Ok(()),
-            other => Err(to_error(other))
-        }
+        self.run(delete(path))
     }
 
     /// Download remote file to current local directory.
     pub fn get(&mut self, remote_path: &str, local_path: &str) -> Result<(), FtpError> {
+        let total = match self.progress {
+            Some(_) => self.size(remote_path).ok(),
+            None => None
+        };
+        // Local file is opened before the transfer starts, so that failure doesn't leave replies of
+        // the server unread. It's emptied only once the server sends the file.
+        let existed = Path::new(local_path).exists();
+        let file = OpenOptions::new().write(true).create(true).truncate(false).open(local_path)?;
         let cmd = FtpCommand::RETR(remote_path);
-        let mut stream = try!(self.init_data_transfer(cmd, FtpTransferType::Binary));
-        let mut file = try!(File::create(local_path));
-        try!(stream.write_all_to(&mut file));
-        try!(self.end_data_transfer());
-        Ok(())
+        let mut stream = match self.init_data_transfer(cmd, FtpTransferType::Binary) {
+            Ok(stream) => stream,
+            Err(err) => {
+                if !existed {
+                    let _ = fs::remove_file(local_path);
+                }
+                return Err(err);
+            }
+        };
+        let mut reporter = ProgressReporter::new(self.progress.take(), total);
+        let rate_limit = self.rate_limit;
+        let copied = file.set_len(0)
+            .and_then(|_| stream.write_all_to_with_progress(&mut Throttled::new(file, rate_limit), |n| reporter.report(n)));
+        let result = match copied {
+            Ok(()) => self.end_data_transfer(),
+            Err(err) => {
+                // Rest of the transfer is aborted to keep the session usable
+                drop(stream);
+                let _ = self.abort_data_transfer();
+                Err(FtpError::from(err))
+            }
+        };
+        self.progress = reporter.finish();
+        result
+    }
+
+    /// Download `length` bytes of remote file starting at `offset` and write them to `ostream`.
+    /// Transfer is aborted as soon as the range is received. Returns number of bytes received,
+    /// which is less than `length` only when the file is shorter.
+    ///
+    /// ```
+    /// # let server = ftp_cli::testing::TestServer::start(&[("archive.zip", "PK\x03\x04 archive content")]);
+    /// # let mut client = ftp_cli::FtpClient::connect(server.address(), None, None).unwrap();
+    /// # client.login("anonymous", "guest", None).unwrap();
+    /// let mut header = Vec::new();
+    /// client.get_range("archive.zip", 0, 4, &mut header).unwrap();
+    /// assert_eq!(header, b"PK\x03\x04");
+    /// ```
+    pub fn get_range<W: Write>(&mut self, remote_path: &str, offset: u64, length: u64, ostream: &mut W) -> Result<u64, FtpError> {
+        self.run(transfer_type(FtpTransferType::Binary))?;
+        self.run(restart(offset))?;
+
+        let cmd = FtpCommand::RETR(remote_path);
+        let stream = self.init_data_connection(cmd)?;
+        let mut data = stream.take(length);
+        let mut reporter = ProgressReporter::new(self.progress.take(), Some(length));
+        let mut received = 0;
+        let copied = data.write_all_to_with_progress(&mut Throttled::new(ostream, self.rate_limit), |n| {
+            received = n;
+            reporter.report(n)
+        });
+        // Close data connection before ABOR, otherwise server may keep waiting for the transfer to finish.
+        drop(data);
+        let result = copied
+            .map_err(FtpError::from)
+            .and_then(|_| match received < length {
+                true => self.end_data_transfer(),
+                false => self.abort_data_transfer()
+            });
+        self.progress = reporter.finish();
+        result.map(|_| received)
     }
 
     /// Make directory on server
     pub fn mkdir(&mut self, path: &str) -> Result<(), FtpError> {
-        let cmd = FtpCommand::MKD(path);
-        try!(self.write_command(cmd));
-        match self.read_response() {
-            Ok((status::PATHNAME_CREATED, _)) => Ok(()),
-            other => Err(to_error(other))
-        }
+        self.run(make_dir(path))
     }
 
     /// List remote directory.
     pub fn list(&mut self, path: &str) -> Result<String, FtpError> {
-        let cmd = FtpCommand::LIST(path);
-        let mut stream = try!(self.init_data_transfer(cmd, FtpTransferType::Text));
+        self.read_listing(FtpCommand::LIST(path))
+    }
+
+    /// List remote directory as structured entries.
+    /// Uses MLSD when server supports it, otherwise parses Unix style LIST output.
+    pub fn list_entries(&mut self, path: &str) -> Result<Vec<RemoteEntry>, FtpError> {
+        match self.read_listing(FtpCommand::MLSD(path)) {
+            Ok(text) => Ok(parse_mlsd(&text)),
+            Err(FtpError::UnexpectedReturnCode(status::COMMAND_NOT_RECOGNIZED, _)) |
+            Err(FtpError::UnexpectedReturnCode(status::COMMAND_NOT_IMPLEMENTED, _)) => {
+                let text = self.list(path)?;
+                Ok(parse_list(&text))
+            }
+            Err(err) => Err(err)
+        }
+    }
+
+    fn read_listing(&mut self, cmd: FtpCommand) -> Result<String, FtpError> {
+        let mut stream = self.init_data_transfer(cmd, FtpTransferType::Text)?;
         let mut buf :Vec<u8> = Vec::new();
-        try!(stream.read_to_end(&mut buf));
-        let text = try!(String::from_utf8(buf));
-        try!(self.end_data_transfer());
+        stream.read_to_end(&mut buf)?;
+        let text = self.encoding.decode(buf)?;
+        self.end_data_transfer()?;
         Ok(text)
     }
 
     /// Upload local file to server current directory.
     pub fn put(&mut self, local_path: &str, remote_path: &str) -> Result<(), FtpError> {
         let cmd = FtpCommand::STOR(remote_path);
-        {
-            let mut stream = try!(self.init_data_transfer(cmd, FtpTransferType::Binary));
-            let mut file = try!(File::open(local_path));
-            try!(file.write_all_to(&mut stream));
-        }
-        try!(self.end_data_transfer());
-        Ok(())
+        let mut file = File::open(local_path)?;
+        let total = file.metadata()?.len();
+        let mut reporter = ProgressReporter::new(self.progress.take(), Some(total));
+        let result = self.init_data_transfer(cmd, FtpTransferType::Binary)
+            .and_then(|stream| {
+                file.write_all_to_with_progress(&mut Throttled::new(stream, self.rate_limit), |n| reporter.report(n))?;
+                self.end_data_transfer()
+            });
+        self.progress = reporter.finish();
+        result
     }
 
     /// Get current working directory on server.
     pub fn pwd(&mut self) -> Result<String, FtpError> {
-        let cmd = FtpCommand::PWD;
-        try!(self.write_command(cmd));
-        match self.read_response() {
-            Ok((status::PATHNAME_CREATED, path)) => Ok(path[1..path.len()-1].to_string()),
-            other => Err(to_error(other))
-        }
+        self.run(current_dir())
     }
 
     /// Send QUIT command to server and close connection (dropping FtpClient).
     pub fn quit(mut self) {
         let cmd = FtpCommand::QUIT;
-        match self.write_command(cmd) {
-            _ => { /* ignore any error here */ }
-        }
+        // ignore any error here
+        let _ = self.write_command(cmd);
     }
 
     /// Remove directory
     pub fn rmdir(&mut self, path: &str) -> Result<(), FtpError> {
-        let cmd = FtpCommand::RMD(path);
-        try!(self.write_command(cmd));
-        match self.read_response() {
-            Ok((status::FILE_ACTION_OK, _)) => Ok(()),
-            other => Err(to_error(other))
+        self.run(remove_dir(path))
+    }
+
+    /// Get size of remote file in bytes.
+    pub fn size(&mut self, path: &str) -> Result<u64, FtpError> {
+        self.run(size(path))
+    }
+
+    /// Get modification time of remote file in seconds since Unix epoch.
+    pub fn modified(&mut self, path: &str) -> Result<i64, FtpError> {
+        self.run(modified(path))
+    }
+
+    /// Send commands of `sequence` and pass it replies until it's done.
+    fn run<'a, S: Sequence<'a>>(&mut self, mut sequence: S) -> Result<S::Output, FtpError> {
+        self.write_command(sequence.start())?;
+        loop {
+            let reply = self.read_response();
+            match sequence.next(reply) {
+                Step::Send(command) => self.write_command(command)?,
+                Step::Read => { }
+                Step::Done(result) => return result
+            }
         }
     }
 
     /// Read response code and text (rest of a line)
     fn read_response(&mut self) -> Result<(i32, String), FtpError> {
-        let mut line = String::new();
-        try!(self.cmd_stream.read_line(&mut line));
-        let pos = match line.find(' ') {
-            Some(pos) => pos,
-            None => return Err(FtpError::InvalidResponse(line))
-        };
-
-        let code = match line[0..pos].parse::<i32>() {
-            Ok(code) => code,
-            Err(_) => return Err(FtpError::InvalidResponse(line))
-        };
-
-        let text = line[pos+1..].trim().to_string();
-        Ok((code, text))
+        let mut buf = Vec::new();
+        self.cmd_stream.read_until(b'\n', &mut buf)?;
+        let line = self.encoding.decode(buf)?;
+        self.trace_reply(&line);
+        parse_reply(&line)
     }
 
     /// Init data transfer and returns stream.
-    fn init_data_transfer(&mut self, command: FtpCommand, transfer: FtpTransferType) -> Result<TcpStream, FtpError> {
-        let cmd = FtpCommand::TYPE(transfer);
-        try!(self.write_command(cmd));
-        match self.read_response() {
-            Ok((status::SUCCESS,_)) => { }
-            other => return Err(to_error(other))
-        };
+    fn init_data_transfer(&mut self, command: FtpCommand, transfer: FtpTransferType) -> Result<Box<dyn Channel>, FtpError> {
+        self.run(transfer_type(transfer))?;
+        self.init_data_connection(command)
+    }
 
+    /// Open data connection for already prepared transfer command.
+    fn init_data_connection(&mut self, command: FtpCommand) -> Result<Box<dyn Channel>, FtpError> {
         match self.mode {
             FtpMode::Active(addr) => self.init_data_transfer_active(command, addr),
             FtpMode::Passive => self.init_data_transfer_passive(command)
         }
     }
 
-    fn init_data_transfer_active(&mut self, command: FtpCommand, addr: SocketAddrV4) -> Result<TcpStream, FtpError> {
-        let listener = try!(TcpListener::bind(addr));
-        try!(self.write_command(FtpCommand::PORT(addr)));
-        match self.read_response() {
-            Ok((status::SUCCESS,_)) => {
-                try!(self.write_command(command));
-                match self.read_response() {
-                    Ok((status::OPEN_DATA_CONNECTION,_)) => {
-                        let (stream, _) = try!(listener.accept());
-                        Ok(stream)
-                    }
-                    other => Err(to_error(other))
-                }
-            }
-            other => Err(to_error(other))
-        }
+    fn init_data_transfer_active(&mut self, command: FtpCommand, addr: SocketAddrV4) -> Result<Box<dyn Channel>, FtpError> {
+        let mut listener = self.transport.listen(addr)?;
+        // Port 0 lets the system pick a free port, server must be told the actual one.
+        let addr = listener.local_addr()?;
+        self.trace_event(&format!("Listening for data connection on {}", addr));
+        self.run(port(addr))?;
+        self.run(open_data(command))?;
+        let stream = listener.accept()?;
+        self.trace_event("Data connection accepted");
+        Ok(stream)
     }
 
-    fn init_data_transfer_passive(&mut self, command: FtpCommand) -> Result<TcpStream, FtpError> {
-        try!(self.write_command(FtpCommand::PASV));
-        match self.read_response() {
-            Ok((status::ENTERING_PASSIVE_MODE,line)) => {
-                let start_pos = line.rfind('(').unwrap() +1;
-                let end_pos = line.rfind(')').unwrap();
-                let substr = line[start_pos..end_pos].to_string();
-                let nums : Vec<u8> = substr.split(',').map(|x| x.parse::<u8>().unwrap()).collect();
-                let ip = Ipv4Addr::new(nums[0],nums[1],nums[2],nums[3]);
-                let port = to_ftp_port(nums[4] as u16, nums[5] as u16);
-                let addr = SocketAddrV4::new(ip,port);
-                try!(self.write_command(command));
-                let stream = try!(TcpStream::connect(addr));
-                match self.read_response() {
-                    Ok((status::OPEN_DATA_CONNECTION,_)) => Ok(stream),
-                    other => Err(to_error(other))
-                }
-            }
-            other => Err(to_error(other))
-        }
+    fn init_data_transfer_passive(&mut self, command: FtpCommand) -> Result<Box<dyn Channel>, FtpError> {
+        let addr = self.run(passive())?;
+        // Reply to the command comes only once the data connection is open
+        self.write_command(command)?;
+        self.trace_event(&format!("Opening data connection to {}", addr));
+        let stream = self.transport.connect_data(addr)?;
+        expect(self.read_response(), status::OPEN_DATA_CONNECTION)?;
+        Ok(stream)
     }
 
     fn end_data_transfer(&mut self) -> Result<(), FtpError> {
-        match self.read_response() {
-            Ok((status::CLOSING_DATA_CONNECTION,_)) => Ok(()),
-            other => Err(to_error(other))
-        }
+        self.trace_event("Data transfer finished");
+        expect(self.read_response(), status::CLOSING_DATA_CONNECTION).map(|_| ())
+    }
+
+    /// Abort running transfer after its data connection was closed by client.
+    fn abort_data_transfer(&mut self) -> Result<(), FtpError> {
+        self.trace_event("Data connection closed before end of transfer");
+        self.run(abort())
     }
 
     fn write_command(&mut self, cmd: FtpCommand) -> Result<(), IoError> {
-        let mut stream = self.cmd_stream.get_mut();
-        try!(stream.write(cmd.to_string().as_bytes()));
-        try!(stream.flush());
+        let text = cmd.to_string();
+        if let Some(ref trace) = self.trace {
+            trace.command(text.trim_end());
+        }
+        let line = self.encoding.encode(&text);
+        let stream = self.cmd_stream.get_mut();
+        stream.write_all(&line)?;
+        stream.flush()?;
         Ok(())
     }
-}
 
-fn to_error(result: Result<(i32,String),FtpError>) -> FtpError {
-    match result {
-        Ok((status::OPERATION_FAILED, text)) => FtpError::OperationFailed(text),
-        Ok((code,text)) => FtpError::UnexpectedReturnCode(code, text),
-        Err(err) => err
+    fn trace_event(&self, text: &str) {
+        if let Some(ref trace) = self.trace {
+            trace.event(text);
+        }
+    }
+
+    fn trace_reply(&self, line: &str) {
+        if let Some(ref trace) = self.trace {
+            trace.reply(line);
+        }
     }
 }
 
+/// Forwards transfer progress to optional handler.
+struct ProgressReporter {
+    handler: Option<ProgressHandler>,
+    total: Option<u64>,
+    start: Instant,
+    transferred: u64,
+}
+
+impl ProgressReporter {
 
+    fn new(handler: Option<ProgressHandler>, total: Option<u64>) -> ProgressReporter {
+        let mut reporter = ProgressReporter {
+            handler,
+            total,
+            start: Instant::now(),
+            transferred: 0,
+        };
+        reporter.send(false);
+        reporter
+    }
 
-fn to_ftp_port(b1: u16, b2: u16) -> u16 {
-    b1 *256 + b2
+    fn report(&mut self, transferred: u64) {
+        self.transferred = transferred;
+        self.send(false);
+    }
+
+    /// Sends final report and gives the handler back.
+    fn finish(mut self) -> Option<ProgressHandler> {
+        self.send(true);
+        self.handler
+    }
+
+    fn send(&mut self, finished: bool) {
+        if let Some(ref mut handler) = self.handler {
+            handler(&TransferProgress {
+                transferred: self.transferred,
+                total: self.total,
+                elapsed: self.start.elapsed(),
+                finished,
+            });
+        }
+    }
 }
 
-mod status {
-    pub const OPEN_DATA_CONNECTION : i32 = 150;
-    pub const SUCCESS : i32 = 200;
-    pub const READY_FOR_NEW_USER : i32 = 220;
-    pub const ENTERING_PASSIVE_MODE : i32 = 227;
-    pub const CLOSING_DATA_CONNECTION : i32 = 226;
-    pub const LOGIN_SUCCESSFUL : i32 = 230;
-    pub const FILE_ACTION_OK : i32 = 250;
-    pub const PATHNAME_CREATED : i32 = 257;
-    pub const USERNAME_OK_NEED_PASSWORD : i32 = 331;
-    pub const INVALID_USERNAME_OR_PASSWORD : i32 = 430;
-    pub const NOT_LOGGED_IN : i32 = 530;
-    pub const OPERATION_FAILED : i32 = 550;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestServer;
+
+    fn login(server: &TestServer) -> FtpClient {
+        let mut client = FtpClient::connect(server.address(), Some(Duration::from_secs(10)), None).unwrap();
+        assert!(client.login("anonymous", "guest", None).unwrap());
+        client
+    }
+
+    #[test]
+    fn failed_local_file_keeps_session_usable() {
+        let server = TestServer::start(&[("/pub/a.txt", "content")]);
+        let root = ::std::env::temp_dir().join(format!("ftp-cli-get-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let mut client = login(&server);
+        client.cd("/pub").unwrap();
+
+        let missing_dir = root.join("missing").join("a.txt");
+        assert!(client.get("a.txt", missing_dir.to_str().unwrap()).is_err());
+        assert_eq!(client.pwd().unwrap(), "/pub");
+
+        let local = root.join("a.txt");
+        client.get("a.txt", local.to_str().unwrap()).unwrap();
+        let content = fs::read_to_string(&local);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(content.unwrap(), "content");
+    }
+
+    #[test]
+    fn failed_download_keeps_local_file() {
+        let server = TestServer::start(&[]);
+        let root = ::std::env::temp_dir().join(format!("ftp-cli-get-missing-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let (existing, new) = (root.join("existing.txt"), root.join("new.txt"));
+        fs::write(&existing, "old").unwrap();
+        let mut client = login(&server);
+
+        assert!(client.get("missing.txt", existing.to_str().unwrap()).is_err());
+        assert!(client.get("missing.txt", new.to_str().unwrap()).is_err());
+        let content = fs::read_to_string(&existing);
+        let created = new.exists();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(content.unwrap(), "old");
+        assert!(!created);
+    }
 }
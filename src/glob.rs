@@ -0,0 +1,231 @@
+//! Shell style wildcard matching and expansion.
+//!
+//! Supported patterns are `*` (any characters), `?` (single character), `[a-z]` and `[!a-z]`
+//! (character classes) within one path component and `**` matching any number of directories.
+
+
+/// Check whether path contains any wildcard characters.
+pub fn has_wildcards(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Match single path component against pattern.
+/// Wildcards don't match leading dot of hidden files.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_from(&pattern, &name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| match_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && match_from(&pattern[1..], &name[1..]),
+        Some('[') => {
+            match (name.first(), parse_class(&pattern[1..])) {
+                (Some(&c), Some((negated, ranges, len))) => {
+                    let found = ranges.iter().any(|&(from, to)| from <= c && c <= to);
+                    found != negated && match_from(&pattern[len + 1..], &name[1..])
+                }
+                // Unclosed bracket is literal character
+                (Some(&c), None) => c == '[' && match_from(&pattern[1..], &name[1..]),
+                (None, _) => false
+            }
+        }
+        Some(&p) => name.first() == Some(&p) && match_from(&pattern[1..], &name[1..])
+    }
+}
+
+/// Parse character class following `[`. Returns negation flag, list of ranges and number of
+/// characters consumed including closing `]`.
+#[allow(clippy::type_complexity)]
+fn parse_class(pattern: &[char]) -> Option<(bool, Vec<(char, char)>, usize)> {
+    let mut pos = 0;
+    let negated = matches!(pattern.first(), Some('!') | Some('^'));
+    if negated {
+        pos += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let start = pos;
+    while pos < pattern.len() {
+        let c = pattern[pos];
+        // `]` right after opening bracket is literal
+        if c == ']' && pos > start {
+            return Some((negated, ranges, pos + 1));
+        }
+        if pos + 2 < pattern.len() && pattern[pos + 1] == '-' && pattern[pos + 2] != ']' {
+            ranges.push((c, pattern[pos + 2]));
+            pos += 3;
+        }
+        else {
+            ranges.push((c, c));
+            pos += 1;
+        }
+    }
+    None
+}
+
+
+/// Expand pattern to matching paths. `list` returns names of entries in given directory
+/// (empty string for current directory) together with a flag whether entry is a directory.
+/// Returns matching paths with the same flag, sorted by name.
+pub fn expand<F, E>(pattern: &str, mut list: F) -> Result<Vec<(String, bool)>, E>
+    where F: FnMut(&str) -> Result<Vec<(String, bool)>, E>
+{
+    let absolute = pattern.starts_with('/');
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let root = match absolute {
+        true => "/".to_string(),
+        false => String::new()
+    };
+
+    let mut results = Vec::new();
+    expand_from(&root, true, &components, &mut list, &mut results)?;
+    results.sort();
+    results.dedup();
+    Ok(results)
+}
+
+fn expand_from<F, E>(base: &str, base_is_dir: bool, components: &[&str], list: &mut F,
+                     results: &mut Vec<(String, bool)>) -> Result<(), E>
+    where F: FnMut(&str) -> Result<Vec<(String, bool)>, E>
+{
+    let component = match components.first() {
+        Some(component) => *component,
+        None => {
+            if !base.is_empty() {
+                results.push((base.to_string(), base_is_dir));
+            }
+            return Ok(());
+        }
+    };
+    let rest = &components[1..];
+
+    if component == "**" {
+        // Zero directories
+        expand_from(base, base_is_dir, rest, list, results)?;
+        // One or more directories
+        for (name, is_dir) in list(base)? {
+            if is_dir && !name.starts_with('.') {
+                expand_from(&join(base, &name), true, components, list, results)?;
+            }
+        }
+    }
+    else if !has_wildcards(component) {
+        // Plain names are not checked, missing files are reported by the transfer itself
+        let is_dir = !rest.is_empty();
+        expand_from(&join(base, component), is_dir, rest, list, results)?;
+    }
+    else {
+        for (name, is_dir) in list(base)? {
+            if matches(component, &name) && (is_dir || rest.is_empty()) {
+                expand_from(&join(base, &name), is_dir, rest, list, results)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn join(base: &str, name: &str) -> String {
+    match base {
+        "" => name.to_string(),
+        _ if base.ends_with('/') => format!("{}{}", base, name),
+        _ => format!("{}/{}", base, name)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_wildcards() {
+        for path in ["*.txt", "a?c", "[ab]", "dir/**/x"].iter() {
+            assert!(has_wildcards(path), "{}", path);
+        }
+        for path in ["a.txt", "dir/file", ""].iter() {
+            assert!(!has_wildcards(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn matches_names() {
+        let cases = [
+            ("*", "file.txt", true),
+            ("*.txt", "file.txt", true),
+            ("*.txt", "file.csv", false),
+            ("f*e*", "fileext", true),
+            ("?.txt", "a.txt", true),
+            ("?.txt", "ab.txt", false),
+            ("[abc].txt", "b.txt", true),
+            ("[abc].txt", "d.txt", false),
+            ("[a-c]x", "cx", true),
+            ("[!a-c]x", "cx", false),
+            ("[^a-c]x", "dx", true),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("[abc", "[abc", true),
+            ("[abc", "a", false),
+            ("*", ".hidden", false),
+            (".*", ".hidden", true),
+            ("plain", "plain", true),
+            ("", "", true),
+            ("*", "", true),
+            ("žlu?", "žluť", true),
+        ];
+        for &(pattern, name, expected) in cases.iter() {
+            assert_eq!(matches(pattern, name), expected, "{} ~ {}", pattern, name);
+        }
+    }
+
+    /// Directory tree given as (path, is directory) pairs.
+    fn list<'a>(tree: &'a [(&'a str, bool)]) -> impl FnMut(&str) -> Result<Vec<(String, bool)>, ()> + 'a {
+        move |dir: &str| {
+            Ok(tree.iter()
+                .filter(|&&(path, _)| match path.rfind('/') {
+                    Some(pos) => &path[..pos] == dir.trim_end_matches('/') || (dir == "/" && pos == 0),
+                    None => dir.is_empty()
+                })
+                .map(|&(path, is_dir)| (path.rsplit('/').next().unwrap().to_string(), is_dir))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn expands_patterns() {
+        let tree = [
+            ("a.txt", false), ("b.csv", false), (".hidden.txt", false),
+            ("dir", true), ("dir/c.txt", false), ("dir/sub", true), ("dir/sub/d.txt", false),
+            ("other", true), ("other/e.txt", false),
+        ];
+        let cases: [(&str, &[(&str, bool)]); 7] = [
+            ("*.txt", &[("a.txt", false)]),
+            ("*", &[("a.txt", false), ("b.csv", false), ("dir", true), ("other", true)]),
+            ("*/*.txt", &[("dir/c.txt", false), ("other/e.txt", false)]),
+            // Plain names aren't looked up
+            ("d*/sub", &[("dir/sub", false)]),
+            ("**/*.txt", &[("a.txt", false), ("dir/c.txt", false), ("dir/sub/d.txt", false), ("other/e.txt", false)]),
+            ("dir/**/*.txt", &[("dir/c.txt", false), ("dir/sub/d.txt", false)]),
+            ("missing/*.txt", &[]),
+        ];
+        for &(pattern, expected) in cases.iter() {
+            let found = expand(pattern, list(&tree)).unwrap();
+            let expected: Vec<_> = expected.iter().map(|&(path, is_dir)| (path.to_string(), is_dir)).collect();
+            assert_eq!(found, expected, "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn keeps_plain_and_absolute_paths() {
+        let tree = [("/pub", true), ("/pub/a.txt", false)];
+        assert_eq!(expand("/pub/*.txt", list(&tree)).unwrap(), vec![("/pub/a.txt".to_string(), false)]);
+        assert_eq!(expand("no/such", list(&tree)).unwrap(), vec![("no/such".to_string(), false)]);
+    }
+}
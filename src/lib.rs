@@ -0,0 +1,51 @@
+//! FTP client library used by the `ftp-cli` program.
+//!
+//! [`FtpClient`] holds single control connection and runs one command at a time. Transfers,
+//! listings and directory operations block until the server finishes them.
+//!
+//! ```
+//! use ftp_cli::{FtpClient, FtpError};
+//!
+//! fn main() -> Result<(), FtpError> {
+//! #   let server = ftp_cli::testing::TestServer::start(&[("/pub/README", "Hello\n")]);
+//! #   let server = server.address();
+//!     let mut client = FtpClient::connect(server, None, None)?;
+//!     if !client.login("anonymous", "guest", None)? {
+//!         return Err(FtpError::LoginFailed("Login refused".to_string()));
+//!     }
+//!     client.cd("/pub")?;
+//!     for entry in client.list_entries(".")? {
+//!         println!("{} {:?}", entry.name, entry.size);
+//!     }
+//!     let local = std::env::temp_dir().join("README");
+//!     client.get("README", local.to_str().unwrap())?;
+//!     client.quit();
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`pool::ConnectionPool`] runs many transfers in parallel over additional sessions,
+//! [`mirror`] and [`sync`] compare and transfer whole directory trees.
+
+pub mod error;
+pub mod ftp_client;
+pub mod stream;
+pub mod commands;
+pub mod pool;
+pub mod listing;
+pub mod tree;
+pub mod glob;
+pub mod mirror;
+pub mod sync;
+pub mod trace;
+pub mod transport;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use crate::error::{ErrorCategory, FtpError};
+pub use crate::ftp_client::{Encoding, FtpClient, FtpMode};
+pub use crate::listing::{EntryKind, RemoteEntry};
+pub use crate::stream::{ProgressHandler, TransferProgress};
+pub use crate::pool::{ConnectionPool, Direction, JobResult, SessionConfig, TransferJob};
+pub use crate::trace::Trace;
+pub use crate::transport::{Channel, TcpTransport, Transport};
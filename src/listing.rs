@@ -0,0 +1,219 @@
+/// Type of remote directory entry.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Link,
+    Other,
+}
+
+/// Single entry of structured directory listing.
+#[derive(Debug, Clone)]
+pub struct RemoteEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: Option<u64>,
+    /// Modification time in seconds since Unix epoch.
+    pub modified: Option<i64>,
+}
+
+
+/// Parse MLSD response. Entries for current and parent directory are skipped.
+pub fn parse_mlsd(text: &str) -> Vec<RemoteEntry> {
+    text.lines().filter_map(parse_mlsd_line).collect()
+}
+
+fn parse_mlsd_line(line: &str) -> Option<RemoteEntry> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    // Facts never contain spaces, name is everything after the first one
+    let pos = line.find(' ')?;
+    let (facts, name) = (&line[..pos], &line[pos+1..]);
+
+    let mut entry = RemoteEntry {
+        name: name.to_string(),
+        kind: EntryKind::Other,
+        size: None,
+        modified: None,
+    };
+
+    for fact in facts.split(';') {
+        let (key, value) = match fact.find('=') {
+            Some(pos) => (fact[..pos].to_lowercase(), &fact[pos+1..]),
+            None => continue
+        };
+        match key.as_str() {
+            "type" => entry.kind = match value.to_lowercase().as_str() {
+                "file" => EntryKind::File,
+                "dir" => EntryKind::Directory,
+                "cdir" | "pdir" => return None,
+                kind if kind.starts_with("os.unix=slink") || kind == "os.unix=symlink" => EntryKind::Link,
+                _ => EntryKind::Other
+            },
+            "size" => entry.size = value.parse::<u64>().ok(),
+            "modify" => entry.modified = parse_time(value),
+            _ => { }
+        }
+    }
+
+    Some(entry)
+}
+
+
+/// Parse Unix style LIST response (`drwxr-xr-x 2 user group 4096 Oct 18 14:14 name`).
+/// Lines in other formats are skipped.
+pub fn parse_list(text: &str) -> Vec<RemoteEntry> {
+    text.lines().filter_map(parse_list_line).collect()
+}
+
+fn parse_list_line(line: &str) -> Option<RemoteEntry> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let mut rest = line;
+    let mut fields = Vec::new();
+    // permissions, links, owner, group, size, month, day, time/year
+    for _ in 0..8 {
+        rest = rest.trim_start();
+        let end = rest.find(' ')?;
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    let name = rest.trim_start();
+
+    let (kind, name) = match fields[0].chars().next()? {
+        'd' => (EntryKind::Directory, name),
+        '-' => (EntryKind::File, name),
+        'l' => (EntryKind::Link, name.split(" -> ").next().unwrap_or(name)),
+        _ => (EntryKind::Other, name)
+    };
+
+    if name == "." || name == ".." {
+        return None;
+    }
+
+    Some(RemoteEntry {
+        name: name.to_string(),
+        kind,
+        size: fields[4].parse::<u64>().ok(),
+        modified: None,
+    })
+}
+
+
+/// Parse time value `YYYYMMDDHHMMSS[.sss]` used by MLSD and MDTM (always UTC).
+/// Returns seconds since Unix epoch.
+pub fn parse_time(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let digits = text.split('.').next()?;
+    if digits.len() != 14 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let num = |from: usize, to: usize| digits[from..to].parse::<i64>().ok();
+    let (year, month, day) = (num(0, 4)?, num(4, 6)?, num(6, 8)?);
+    let (hour, min, sec) = (num(8, 10)?, num(10, 12)?, num(12, 14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    // Days from civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + hour * 3600 + min * 60 + sec)
+}
+
+
+/// Check that name received from server is a plain file name which can't escape
+/// the target directory when joined to a local path.
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains('/')
+        && !name.contains('\\')
+        && !name.contains('\0')
+        && !(name.len() >= 2 && name.as_bytes()[1] == b':')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(entries: &[RemoteEntry]) -> Vec<(&str, EntryKind, Option<u64>, Option<i64>)> {
+        entries.iter().map(|e| (e.name.as_str(), e.kind, e.size, e.modified)).collect()
+    }
+
+    #[test]
+    fn parses_mlsd() {
+        let text = "type=cdir;modify=20240102030405; .\r\n\
+                    type=pdir;modify=20240102030405; ..\r\n\
+                    type=file;size=5;modify=20240102030405; a b.txt\r\n\
+                    Type=DIR;Modify=20000229120000.123; sub\r\n\
+                    type=OS.unix=slink:/etc/target;size=11; link\r\n\
+                    type=OS.unix=symlink; other link\r\n\
+                    type=file;size=huge;modify=yesterday; odd\r\n\
+                    unix.mode=0644;perm=r; unknown\r\n\
+                    no-space-in-line\r\n";
+        assert_eq!(fields(&parse_mlsd(text)), vec![
+            ("a b.txt", EntryKind::File, Some(5), Some(1704164645)),
+            ("sub", EntryKind::Directory, None, Some(951825600)),
+            ("link", EntryKind::Link, Some(11), None),
+            ("other link", EntryKind::Link, None, None),
+            ("odd", EntryKind::File, None, None),
+            ("unknown", EntryKind::Other, None, None),
+        ]);
+    }
+
+    #[test]
+    fn parses_list() {
+        let text = "total 12\r\n\
+                    drwxr-xr-x 2 user group 4096 Oct 18 14:14 .\r\n\
+                    drwxr-xr-x 5 user group 4096 Oct 18 14:14 ..\r\n\
+                    drwxr-xr-x 2 user group 4096 Oct 18 14:14 dir name\r\n\
+                    -rw-r--r--   1 user   group   123 Jan  2  2024 file.txt\r\n\
+                    lrwxrwxrwx 1 user group 7 Jan 02 2024 link -> target\r\n\
+                    crw-r--r-- 1 root root 0 Jan 02 2024 device\r\n\
+                    01-02-24  03:04AM       <DIR>          windows\r\n";
+        assert_eq!(fields(&parse_list(text)), vec![
+            ("dir name", EntryKind::Directory, Some(4096), None),
+            ("file.txt", EntryKind::File, Some(123), None),
+            ("link", EntryKind::Link, Some(7), None),
+            ("device", EntryKind::Other, Some(0), None),
+        ]);
+    }
+
+    #[test]
+    fn parses_time() {
+        let cases = [
+            ("19700101000000", Some(0)),
+            ("20240102030405", Some(1704164645)),
+            ("20000229120000.123", Some(951825600)),
+            (" 20240102030405\r\n", Some(1704164645)),
+            ("19691231235959", Some(-1)),
+            ("2024010203040", None),
+            ("202401020304050", None),
+            ("2024010203040x", None),
+            ("20241302030405", None),
+            ("20240100030405", None),
+            ("20240102240405", None),
+            ("", None),
+        ];
+        for &(text, expected) in cases.iter() {
+            assert_eq!(parse_time(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn checks_safe_names() {
+        for name in ["file.txt", "..hidden", "a b", "žluť"].iter() {
+            assert!(is_safe_name(name), "{}", name);
+        }
+        for name in ["", ".", "..", "a/b", "/etc", r"a\b", "a\0b", "C:", "c:file"].iter() {
+            assert!(!is_safe_name(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::FtpError;
+use crate::ftp_client::FtpClient;
+use crate::pool::{Direction, JobResult, TransferJob};
+use crate::tree::{join_remote, walk_remote};
+
+/// Difference of modification times (in seconds) still considered equal.
+/// Covers filesystems with 2 second resolution and servers without sub-second precision.
+const TIME_TOLERANCE: i64 = 2;
+
+
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// `Download` mirrors remote tree to local one, `Upload` the other way round.
+    pub direction: Direction,
+    /// Remove files and directories missing on the source side.
+    pub delete: bool,
+}
+
+/// Single step of mirroring.
+#[derive(Debug, Clone)]
+pub enum MirrorAction {
+    CreateDir(String),
+    /// Transfer of file which is missing (`true`) or changed (`false`) on target side.
+    Transfer(TransferJob, bool),
+    DeleteFile(String),
+    DeleteDir(String),
+}
+
+impl Display for MirrorAction {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        match *self {
+            MirrorAction::CreateDir(ref path) => write!(f, "mkdir  {}", path),
+            MirrorAction::Transfer(ref job, new) => {
+                let kind = if new { "new" } else { "update" };
+                match job.direction {
+                    Direction::Download => write!(f, "get    {} -> {} ({})", job.remote_path, job.local_path, kind),
+                    Direction::Upload => write!(f, "put    {} -> {} ({})", job.local_path, job.remote_path, kind),
+                }
+            }
+            MirrorAction::DeleteFile(ref path) => write!(f, "rm     {}", path),
+            MirrorAction::DeleteDir(ref path) => write!(f, "rmdir  {}", path),
+        }
+    }
+}
+
+
+/// File or directory found on one side of the mirror. Paths are relative to mirrored root.
+#[derive(Debug, Clone, Copy)]
+pub struct FileState {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<i64>,
+}
+
+/// Collect state of all entries in remote tree.
+pub fn remote_state(client: &mut FtpClient, root: &str) -> Result<BTreeMap<String, FileState>, FtpError> {
+    let tree = walk_remote(client, root)?;
+    let mut state = BTreeMap::new();
+    for dir in tree.dirs {
+        state.insert(dir, FileState { is_dir: true, size: 0, modified: None });
+    }
+    for (path, entry) in tree.files {
+        state.insert(path, FileState {
+            is_dir: false,
+            size: entry.size.unwrap_or(0),
+            modified: entry.modified,
+        });
+    }
+    Ok(state)
+}
+
+/// Collect state of all entries in local tree. Symbolic links are followed.
+pub fn local_state(root: &Path) -> Result<BTreeMap<String, FileState>, FtpError> {
+    let mut state = BTreeMap::new();
+    if !root.exists() {
+        return Ok(state);
+    }
+
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let relative = join_remote(&dir, &entry.file_name().to_string_lossy());
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => continue      // broken link
+            };
+            if metadata.is_dir() {
+                pending.push(relative.clone());
+            }
+            state.insert(relative, FileState {
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok().and_then(to_unix_time),
+            });
+        }
+    }
+    Ok(state)
+}
+
+
+/// Compare local and remote trees and plan actions making target identical to source.
+pub fn plan(client: &mut FtpClient, local_root: &Path, remote_root: &str, options: &MirrorOptions) -> Result<Vec<MirrorAction>, FtpError> {
+    let mut actions = Vec::new();
+    let local = local_state(local_root)?;
+    let remote = match remote_state(client, remote_root) {
+        Ok(remote) => remote,
+        // Remote directory doesn't exist yet
+        Err(FtpError::FileUnavailable(_)) if options.direction == Direction::Upload => {
+            actions.push(MirrorAction::CreateDir(remote_root.to_string()));
+            BTreeMap::new()
+        }
+        Err(err) => return Err(err)
+    };
+    let (source, target) = match options.direction {
+        Direction::Download => (&remote, &local),
+        Direction::Upload => (&local, &remote),
+    };
+
+    let local_path = |path: &str| local_root.join(path).to_string_lossy().into_owned();
+    let target_path = |path: &str| match options.direction {
+        Direction::Download => local_path(path),
+        Direction::Upload => join_remote(remote_root, path),
+    };
+
+    if options.direction == Direction::Download && !local_root.exists() {
+        actions.push(MirrorAction::CreateDir(local_root.to_string_lossy().into_owned()));
+    }
+
+    // Target entries removed while planning, they mustn't be deleted again
+    let mut removed = BTreeSet::new();
+
+    // BTreeMap keeps parents before children
+    for (path, state) in source {
+        let existing = target.get(path);
+        if state.is_dir {
+            match existing {
+                Some(existing) if existing.is_dir => { }
+                Some(_) => {
+                    actions.push(MirrorAction::DeleteFile(target_path(path)));
+                    actions.push(MirrorAction::CreateDir(target_path(path)));
+                }
+                None => actions.push(MirrorAction::CreateDir(target_path(path)))
+            }
+            continue;
+        }
+
+        let new = match existing {
+            None => true,
+            Some(existing) if existing.is_dir => {
+                // Directory is in the way of the file. It can be removed only when empty,
+                // so its content goes first, children before their parents.
+                let prefix = format!("{}/", path);
+                let children: Vec<_> = target.range(prefix.clone()..)
+                    .take_while(|&(child, _)| child.starts_with(&prefix))
+                    .collect();
+                for (child, child_state) in children.into_iter().rev() {
+                    actions.push(match child_state.is_dir {
+                        true => MirrorAction::DeleteDir(target_path(child)),
+                        false => MirrorAction::DeleteFile(target_path(child))
+                    });
+                    removed.insert(child.clone());
+                }
+                actions.push(MirrorAction::DeleteDir(target_path(path)));
+                true
+            }
+            Some(existing) => {
+                let modified = match (options.direction, existing.modified) {
+                    // LIST fallback gives no usable time, ask for it
+                    (Direction::Upload, None) => client.modified(&join_remote(remote_root, path)).ok(),
+                    (_, time) => time
+                };
+                let source_modified = match (options.direction, state.modified) {
+                    (Direction::Download, None) => client.modified(&join_remote(remote_root, path)).ok(),
+                    (_, time) => time
+                };
+                if !is_changed(state.size, source_modified, existing.size, modified) {
+                    continue;
+                }
+                false
+            }
+        };
+
+        let remote_path = join_remote(remote_root, path);
+        let job = match options.direction {
+            Direction::Download => TransferJob::download(&remote_path, &local_path(path)),
+            Direction::Upload => TransferJob::upload(&local_path(path), &remote_path),
+        };
+        actions.push(MirrorAction::Transfer(job, new));
+    }
+
+    if options.delete {
+        // Reverse order removes children before their parent directories
+        for (path, state) in target.iter().rev() {
+            if source.contains_key(path) || removed.contains(path) {
+                continue;
+            }
+            match state.is_dir {
+                true => actions.push(MirrorAction::DeleteDir(target_path(path))),
+                false => actions.push(MirrorAction::DeleteFile(target_path(path)))
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+/// Source file needs to be transferred when size differs or it's newer than the target.
+fn is_changed(size: u64, modified: Option<i64>, target_size: u64, target_modified: Option<i64>) -> bool {
+    if size != target_size {
+        return true;
+    }
+    match (modified, target_modified) {
+        (Some(source), Some(target)) => source > target + TIME_TOLERANCE,
+        // Without timestamps size is all we have
+        _ => false
+    }
+}
+
+
+/// Summary of executed mirror.
+#[derive(Debug, Default)]
+pub struct MirrorSummary {
+    /// Created and deleted files and directories.
+    pub actions: usize,
+    pub transferred: usize,
+    pub failed: usize,
+}
+
+/// Execute planned actions. Directories are created and removed first, file transfers are then
+/// handed over to `transfer` in one batch so they can run in parallel.
+/// Errors are reported through `report` and don't stop the mirror.
+pub fn execute<T, R>(client: &mut FtpClient, actions: Vec<MirrorAction>, direction: Direction,
+                     transfer: T, mut report: R) -> MirrorSummary
+    where T: FnOnce(&mut FtpClient, Vec<TransferJob>) -> Vec<JobResult>,
+          R: FnMut(&str, FtpError)
+{
+    let mut summary = MirrorSummary::default();
+    let mut jobs = Vec::new();
+
+    for action in actions {
+        let (path, result) = match (action, direction) {
+            (MirrorAction::Transfer(job, _), _) => {
+                jobs.push(job);
+                continue;
+            }
+            (MirrorAction::CreateDir(path), Direction::Download) => { let r = fs::create_dir_all(&path).map_err(FtpError::from); (path, r) }
+            (MirrorAction::CreateDir(path), Direction::Upload) => { let r = client.mkdir(&path); (path, r) }
+            (MirrorAction::DeleteFile(path), Direction::Download) => { let r = fs::remove_file(&path).map_err(FtpError::from); (path, r) }
+            (MirrorAction::DeleteFile(path), Direction::Upload) => { let r = client.delete(&path); (path, r) }
+            (MirrorAction::DeleteDir(path), Direction::Download) => { let r = fs::remove_dir_all(&path).map_err(FtpError::from); (path, r) }
+            (MirrorAction::DeleteDir(path), Direction::Upload) => { let r = client.rmdir(&path); (path, r) }
+        };
+        match result {
+            Ok(()) => summary.actions += 1,
+            Err(e) => {
+                summary.failed += 1;
+                report(&path, e);
+            }
+        }
+    }
+
+    // Downloaded files get remote modification time so they aren't seen as changed next time
+    let mut times = Vec::new();
+    if direction == Direction::Download {
+        for job in &jobs {
+            times.push(client.modified(&job.remote_path).ok());
+        }
+    }
+
+    for (index, result) in transfer(client, jobs).into_iter().enumerate() {
+        match result.result {
+            Ok(()) => {
+                summary.transferred += 1;
+                if let Some(&Some(time)) = times.get(index) {
+                    let _ = set_modified(Path::new(&result.job.local_path), time);
+                }
+            }
+            Err(_) => summary.failed += 1
+        }
+    }
+
+    summary
+}
+
+
+fn to_unix_time(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs() as i64)
+}
+
+/// Set modification time of local file.
+pub fn set_modified(path: &Path, time: i64) -> Result<(), FtpError> {
+    let file = File::options().write(true).open(path)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(time.max(0) as u64))?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::testing::TestServer;
+
+    /// Modification time of every file on `TestServer`.
+    const REMOTE_TIME: i64 = 1704164645;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("ftp-cli-mirror-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(root: &Path, path: &str, content: &str, modified: i64) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        set_modified(&path, modified).unwrap();
+    }
+
+    fn plan_of(server: &TestServer, local_root: &Path, remote_root: &str, direction: Direction, delete: bool) -> Vec<String> {
+        let mut client = FtpClient::connect(server.address(), Some(Duration::from_secs(10)), None).unwrap();
+        client.login("anonymous", "guest", None).unwrap();
+        let options = MirrorOptions { direction, delete };
+        let actions = plan(&mut client, local_root, remote_root, &options).unwrap();
+        let local_root = local_root.to_string_lossy();
+        actions.iter().map(|action| action.to_string().replace(local_root.as_ref(), "L")).collect()
+    }
+
+    #[test]
+    fn detects_changes_within_time_tolerance() {
+        let cases = [
+            (3, Some(100), 3, Some(100), false),
+            (3, Some(100), 4, Some(100), true),
+            (4, None, 3, None, true),
+            (3, Some(100 + TIME_TOLERANCE), 3, Some(100), false),
+            (3, Some(101 + TIME_TOLERANCE), 3, Some(100), true),
+            // Older source is never copied over newer target
+            (3, Some(50), 3, Some(100), false),
+            (3, None, 3, Some(100), false),
+            (3, Some(100), 3, None, false),
+        ];
+        for &(size, modified, target_size, target_modified, changed) in cases.iter() {
+            assert_eq!(is_changed(size, modified, target_size, target_modified), changed,
+                       "{:?}", (size, modified, target_size, target_modified));
+        }
+    }
+
+    #[test]
+    fn plans_download() {
+        let server = TestServer::start(&[
+            ("/m/bigger.txt", "12345"),
+            ("/m/close.txt", "abc"),
+            ("/m/dir2/f", "f"),
+            ("/m/new.txt", "x"),
+            ("/m/newer.txt", "abc"),
+            ("/m/same.txt", "abc"),
+            ("/m/sub/deep.txt", "d"),
+            ("/m/swap", "s"),
+        ]);
+        let root = temp_dir("download");
+        write(&root, "bigger.txt", "123", REMOTE_TIME);
+        write(&root, "close.txt", "abc", REMOTE_TIME - TIME_TOLERANCE);
+        write(&root, "dir2", "was file", REMOTE_TIME);
+        write(&root, "extra.txt", "e", REMOTE_TIME);
+        write(&root, "newer.txt", "abc", REMOTE_TIME - TIME_TOLERANCE - 1);
+        write(&root, "same.txt", "abc", REMOTE_TIME);
+        write(&root, "swap/inner/file", "i", REMOTE_TIME);
+
+        let kept = plan_of(&server, &root, "/m", Direction::Download, false);
+        let deleted = plan_of(&server, &root, "/m", Direction::Download, true);
+        fs::remove_dir_all(&root).unwrap();
+
+        let expected = vec![
+            "get    /m/bigger.txt -> L/bigger.txt (update)",
+            "rm     L/dir2",
+            "mkdir  L/dir2",
+            "get    /m/dir2/f -> L/dir2/f (new)",
+            "get    /m/new.txt -> L/new.txt (new)",
+            "get    /m/newer.txt -> L/newer.txt (update)",
+            "mkdir  L/sub",
+            "get    /m/sub/deep.txt -> L/sub/deep.txt (new)",
+            // Directory replaced by file is emptied first, deepest entries first
+            "rm     L/swap/inner/file",
+            "rmdir  L/swap/inner",
+            "rmdir  L/swap",
+            "get    /m/swap -> L/swap (new)",
+        ];
+        assert_eq!(kept, expected);
+        let mut expected = expected;
+        expected.push("rm     L/extra.txt");
+        assert_eq!(deleted, expected);
+    }
+
+    #[test]
+    fn plans_upload() {
+        let server = TestServer::start(&[
+            ("/m/close.txt", "abc"),
+            ("/m/dir/f", "f"),
+            ("/m/extra/old.txt", "o"),
+            ("/m/newer.txt", "abc"),
+        ]);
+        let root = temp_dir("upload");
+        write(&root, "close.txt", "abc", REMOTE_TIME + TIME_TOLERANCE);
+        write(&root, "dir", "now a file", REMOTE_TIME);
+        write(&root, "newer.txt", "abc", REMOTE_TIME + TIME_TOLERANCE + 1);
+
+        let existing = plan_of(&server, &root, "/m", Direction::Upload, true);
+        let missing = plan_of(&server, &root, "/missing", Direction::Upload, true);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(existing, vec![
+            "rm     /m/dir/f",
+            "rmdir  /m/dir",
+            "put    L/dir -> /m/dir (new)",
+            "put    L/newer.txt -> /m/newer.txt (update)",
+            "rm     /m/extra/old.txt",
+            "rmdir  /m/extra",
+        ]);
+        assert_eq!(missing, vec![
+            "mkdir  /missing",
+            "put    L/close.txt -> /missing/close.txt (new)",
+            "put    L/dir -> /missing/dir (new)",
+            "put    L/newer.txt -> /missing/newer.txt (new)",
+        ]);
+    }
+}
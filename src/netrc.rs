@@ -0,0 +1,280 @@
+//! Credentials stored in `.netrc` file.
+//!
+//! The file consists of `machine NAME` and `default` entries followed by `login`, `password`
+//! and `account` tokens. `macdef NAME` defines macro of the entry, its commands are on following
+//! lines up to an empty line.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ftp_cli::error::FtpError;
+
+
+/// Named list of commands.
+#[derive(Debug, Clone, Default)]
+pub struct Macro {
+    pub name: String,
+    pub commands: Vec<String>,
+}
+
+/// Credentials and macros of single machine.
+#[derive(Debug, Clone, Default)]
+pub struct NetrcEntry {
+    pub login: Option<String>,
+    pub password: Option<String>,
+    pub account: Option<String>,
+    pub macros: Vec<Macro>,
+}
+
+#[derive(Debug, Default)]
+pub struct Netrc {
+    machines: Vec<(String, NetrcEntry)>,
+    default: Option<NetrcEntry>,
+}
+
+impl Netrc {
+
+    /// Location of the file given by `NETRC` variable or `.netrc` in home directory.
+    pub fn path() -> Option<PathBuf> {
+        match ::std::env::var_os("NETRC") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::home_dir().map(|home| home.join(".netrc"))
+        }
+    }
+
+    /// Read and parse the file. Files accessible by other users are refused as they may leak passwords.
+    pub fn load(path: &Path) -> Result<Netrc, FtpError> {
+        let metadata = fs::metadata(path)?;
+        check_permissions(path, &metadata)?;
+        let text = fs::read_to_string(path)?;
+        Netrc::parse(&text).map_err(|e| FtpError::OperationFailed(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(text: &str) -> Result<Netrc, String> {
+        let mut netrc = Netrc::default();
+        let mut tokens = Tokens { text, pos: 0 };
+        // Entry being filled and machine name (`None` for default entry)
+        let mut current: Option<(Option<String>, NetrcEntry)> = None;
+
+        while let Some(token) = tokens.next() {
+            match token.as_str() {
+                "machine" | "default" => {
+                    if let Some(entry) = current.take() {
+                        netrc.add(entry);
+                    }
+                    let name = match token.as_str() {
+                        "machine" => Some(tokens.value("machine")?),
+                        _ => None
+                    };
+                    current = Some((name, NetrcEntry::default()));
+                }
+                "login" | "password" | "account" => {
+                    let value = tokens.value(&token)?;
+                    let entry = match current {
+                        Some((_, ref mut entry)) => entry,
+                        None => return Err(format!("\"{}\" outside of machine entry.", token))
+                    };
+                    match token.as_str() {
+                        "login" => entry.login = Some(value),
+                        "password" => entry.password = Some(value),
+                        _ => entry.account = Some(value)
+                    }
+                }
+                "macdef" => {
+                    let name = tokens.value("macdef")?;
+                    let commands = tokens.macro_body();
+                    match current {
+                        Some((_, ref mut entry)) => entry.macros.push(Macro { name, commands }),
+                        None => return Err("\"macdef\" outside of machine entry.".to_string())
+                    }
+                }
+                other => return Err(format!("Unknown token \"{}\".", other))
+            }
+        }
+
+        if let Some(entry) = current.take() {
+            netrc.add(entry);
+        }
+        Ok(netrc)
+    }
+
+    fn add(&mut self, (name, entry): (Option<String>, NetrcEntry)) {
+        match name {
+            Some(name) => self.machines.push((name, entry)),
+            None => self.default = Some(entry)
+        }
+    }
+
+    /// Entry of given host, or default entry when there is none.
+    pub fn find(&self, host: &str) -> Option<&NetrcEntry> {
+        self.machines.iter()
+            .find(|&(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, entry)| entry)
+            .or(self.default.as_ref())
+    }
+}
+
+
+#[cfg(unix)]
+fn check_permissions(path: &Path, metadata: &fs::Metadata) -> Result<(), FtpError> {
+    use std::os::unix::fs::PermissionsExt;
+    match metadata.permissions().mode() & 0o077 {
+        0 => Ok(()),
+        _ => Err(FtpError::OperationFailed(format!(
+            "{} is accessible by other users and won't be used. Restrict it with chmod 600.", path.display())))
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path, _metadata: &fs::Metadata) -> Result<(), FtpError> {
+    Ok(())
+}
+
+
+/// Whitespace separated tokens. Values may be enclosed in double quotes.
+struct Tokens<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+
+    fn next(&mut self) -> Option<String> {
+        let rest = &self.text[self.pos..];
+        let start = self.pos + rest.len() - rest.trim_start().len();
+        let rest = &self.text[start..];
+        if rest.is_empty() {
+            self.pos = start;
+            return None;
+        }
+
+        let mut token = String::new();
+        let mut chars = rest.char_indices();
+        let mut end = rest.len();
+        if rest.starts_with('"') {
+            chars.next();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    '\\' => if let Some((_, c)) = chars.next() {
+                        token.push(c);
+                    },
+                    c => token.push(c)
+                }
+            }
+        }
+        else {
+            for (i, c) in chars {
+                if c.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                token.push(c);
+            }
+        }
+        self.pos = start + end;
+        Some(token)
+    }
+
+    fn value(&mut self, keyword: &str) -> Result<String, String> {
+        self.next().ok_or_else(|| format!("Missing value of \"{}\".", keyword))
+    }
+
+    /// Lines following the current one up to the first empty line.
+    fn macro_body(&mut self) -> Vec<String> {
+        let rest = &self.text[self.pos..];
+        let body = match rest.find('\n') {
+            Some(pos) => &rest[pos + 1..],
+            None => ""
+        };
+        let mut commands = Vec::new();
+        let mut consumed = rest.len() - body.len();
+        for line in body.split_inclusive('\n') {
+            consumed += line.len();
+            if line.trim().is_empty() {
+                break;
+            }
+            commands.push(line.trim_end().to_string());
+        }
+        self.pos += consumed;
+        commands
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_machine_entries() {
+        let netrc = Netrc::parse("machine ftp.example.com login alice password secret\n\
+                                  machine other.org\n  login bob\n  password \"two words\"\n  account acct\n\
+                                  default login anonymous password guest@").unwrap();
+        let cases = [
+            ("ftp.example.com", "alice", "secret", None),
+            ("FTP.Example.COM", "alice", "secret", None),
+            ("other.org", "bob", "two words", Some("acct")),
+            ("unknown.net", "anonymous", "guest@", None),
+        ];
+        for &(host, login, password, account) in cases.iter() {
+            let entry = netrc.find(host).unwrap();
+            assert_eq!(entry.login.as_deref(), Some(login), "{}", host);
+            assert_eq!(entry.password.as_deref(), Some(password), "{}", host);
+            assert_eq!(entry.account.as_deref(), account, "{}", host);
+        }
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let cases = [
+            ("machine h password \"a b\"", "a b"),
+            ("machine h password \"with \\\"quote\\\"\"", "with \"quote\""),
+            ("machine h password \"back\\\\slash\"", "back\\slash"),
+            ("machine h password \"\"", ""),
+            ("machine h password \"unterminated", "unterminated"),
+            ("machine h password p\"q", "p\"q"),
+        ];
+        for &(text, password) in cases.iter() {
+            let netrc = Netrc::parse(text).unwrap();
+            assert_eq!(netrc.find("h").unwrap().password.as_deref(), Some(password), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_macros_up_to_empty_line() {
+        let netrc = Netrc::parse("machine h login u\n\
+                                  macdef init\n  cd /pub\nbinary\n\n\
+                                  macdef last\nls\n").unwrap();
+        let macros = &netrc.find("h").unwrap().macros;
+        assert_eq!(macros.len(), 2);
+        assert_eq!(macros[0].name, "init");
+        assert_eq!(macros[0].commands, vec!["  cd /pub", "binary"]);
+        assert_eq!(macros[1].name, "last");
+        assert_eq!(macros[1].commands, vec!["ls"]);
+    }
+
+    #[test]
+    fn without_default_unknown_host_has_no_entry() {
+        let netrc = Netrc::parse("machine h login u").unwrap();
+        assert!(netrc.find("other").is_none());
+        assert!(Netrc::parse("").unwrap().find("h").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let cases = [
+            "login u",
+            "macdef m\nls\n",
+            "machine",
+            "machine h login",
+            "machine h port 21",
+        ];
+        for text in cases.iter() {
+            assert!(Netrc::parse(text).is_err(), "{}", text);
+        }
+    }
+}
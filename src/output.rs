@@ -0,0 +1,130 @@
+//! Results of commands printed either as text for people or as JSON objects for other programs.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use ftp_cli::error::FtpError;
+use ftp_cli::pool::{Direction, JobResult};
+
+
+/// Result of single command. In text mode messages are printed right away. In JSON mode
+/// messages are dropped and fields set by the command are printed as one object when it finishes.
+pub struct Output {
+    json: bool,
+    fields: Map<String, Value>,
+}
+
+impl Output {
+
+    pub fn new(json: bool) -> Output {
+        Output {
+            json,
+            fields: Map::new(),
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Print message for people, it's not part of JSON output.
+    pub fn message(&self, text: &str) {
+        if !self.json {
+            println!("{}", text);
+        }
+    }
+
+    /// Set field of JSON result.
+    pub fn set<V: Into<Value>>(&mut self, key: &str, value: V) {
+        if self.json {
+            self.fields.insert(key.to_string(), value.into());
+        }
+    }
+
+    /// Append value to array field of JSON result.
+    pub fn push(&mut self, key: &str, value: Value) {
+        if !self.json {
+            return;
+        }
+        match self.fields.entry(key.to_string()).or_insert_with(|| Value::Array(Vec::new())) {
+            Value::Array(ref mut values) => values.push(value),
+            _ => panic!("Field {} is not an array.", key)
+        }
+    }
+
+    /// Report failure of single file or pattern when command goes on with the others.
+    pub fn fail(&mut self, path: &str, error: &FtpError) {
+        match self.json {
+            true => self.push("failures", json!({ "path": path, "error": error_value(error) })),
+            false => println!("{}: {}", path, error)
+        }
+    }
+
+    /// Record finished transfer. Checksum of local file is computed only for JSON output.
+    pub fn transfer(&mut self, result: &JobResult) {
+        if !self.json {
+            return;
+        }
+        let job = &result.job;
+        let mut value = json!({
+            "direction": match job.direction {
+                Direction::Download => "download",
+                Direction::Upload => "upload"
+            },
+            "remote": job.remote_path,
+            "local": job.local_path,
+            "ok": result.result.is_ok(),
+            "bytes": result.bytes,
+            "duration": result.duration.as_secs_f64(),
+        });
+        match result.result {
+            Ok(()) => value["sha256"] = checksum(Path::new(&job.local_path)).map_or(Value::Null, Value::String),
+            Err(ref e) => value["error"] = error_value(e)
+        }
+        self.push("transfers", value);
+    }
+
+    /// Print JSON object with name of the command, its result and fields set by it.
+    pub fn finish(self, command: &str, result: &Result<(), FtpError>) {
+        if !self.json {
+            return;
+        }
+        let mut object = Map::new();
+        object.insert("command".to_string(), Value::from(command));
+        object.insert("ok".to_string(), Value::from(result.is_ok()));
+        if let Err(ref e) = *result {
+            object.insert("error".to_string(), error_value(e));
+        }
+        object.extend(self.fields);
+        println!("{}", Value::Object(object));
+    }
+}
+
+
+/// Error with its message, FTP reply code and category.
+pub fn error_value(error: &FtpError) -> Value {
+    json!({
+        "message": error.to_string(),
+        "code": error.reply_code(),
+        "category": error.category().name(),
+    })
+}
+
+/// SHA-256 of file content as hex string.
+fn checksum(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(_) => return None
+        }
+    }
+    Some(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
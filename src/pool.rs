@@ -0,0 +1,594 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::net::SocketAddrV4;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::FtpError;
+use crate::ftp_client::{Encoding, FtpClient, FtpMode};
+use crate::stream::{ProgressHandler, TransferProgress};
+use crate::trace::Trace;
+use crate::transport::Transport;
+
+/// Smallest byte range worth opening another connection for.
+const MIN_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+
+/// Everything needed to open another logged-in session to the same server.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SessionConfig {
+    pub server: String,
+    pub user: String,
+    pub password: String,
+    pub account: Option<String>,
+    pub encoding: Encoding,
+    /// Transport of the main session, new sessions connect the same way.
+    pub transport: Arc<dyn Transport>,
+    pub mode: FtpMode,
+    pub rate_limit: Option<u64>,
+    /// Trace of the main session, new sessions are traced as well.
+    pub trace: Option<Trace>,
+}
+
+impl SessionConfig {
+
+    /// Settings for sessions connected by `transport` in passive mode without speed limit.
+    /// Other settings may be changed through public fields.
+    pub fn new(server: &str, user: &str, password: &str, transport: Arc<dyn Transport>) -> SessionConfig {
+        SessionConfig {
+            server: server.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            account: None,
+            encoding: Encoding::Utf8,
+            transport,
+            mode: FtpMode::Passive,
+            rate_limit: None,
+            trace: None,
+        }
+    }
+
+    /// Connects and logs in a new session.
+    pub fn open(&self) -> Result<FtpClient, FtpError> {
+        let trace = self.trace.as_ref().map(|trace| trace.new_session());
+        let mut client = FtpClient::connect_transport(&self.server, self.transport.clone(), trace)?;
+        match client.login(&self.user, &self.password, self.account.as_deref())? {
+            true => {
+                // Sessions run concurrently, each needs its own port for active data connections.
+                client.set_mode(match self.mode {
+                    FtpMode::Active(addr) => FtpMode::Active(SocketAddrV4::new(*addr.ip(), 0)),
+                    FtpMode::Passive => FtpMode::Passive
+                });
+                client.set_encoding(self.encoding);
+                client.set_rate_limit(self.rate_limit);
+                Ok(client)
+            }
+            false => Err(FtpError::LoginFailed("Invalid username or password.".to_string()))
+        }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+/// Single file transfer executed by the pool.
+#[derive(Debug, Clone)]
+pub struct TransferJob {
+    pub direction: Direction,
+    pub remote_path: String,
+    pub local_path: String,
+}
+
+impl TransferJob {
+
+    pub fn download(remote_path: &str, local_path: &str) -> TransferJob {
+        TransferJob {
+            direction: Direction::Download,
+            remote_path: remote_path.to_string(),
+            local_path: local_path.to_string(),
+        }
+    }
+
+    pub fn upload(local_path: &str, remote_path: &str) -> TransferJob {
+        TransferJob {
+            direction: Direction::Upload,
+            remote_path: remote_path.to_string(),
+            local_path: local_path.to_string(),
+        }
+    }
+
+    /// Runs the job on given session.
+    pub fn run(&self, client: &mut FtpClient) -> Result<(), FtpError> {
+        match self.direction {
+            Direction::Download => client.get(&self.remote_path, &self.local_path),
+            Direction::Upload => client.put(&self.local_path, &self.remote_path),
+        }
+    }
+}
+
+/// Outcome of single job.
+#[derive(Debug)]
+pub struct JobResult {
+    pub job: TransferJob,
+    pub bytes: u64,
+    pub duration: Duration,
+    pub result: Result<(), FtpError>,
+}
+
+
+/// Aggregated state of all jobs submitted to the pool.
+#[derive(Debug, Copy, Clone)]
+pub struct BatchProgress {
+    pub files_total: usize,
+    pub files_done: usize,
+    pub files_failed: usize,
+    /// Bytes transferred by all sessions (including files still in progress).
+    pub transferred: u64,
+    pub elapsed: Duration,
+    pub finished: bool,
+}
+
+/// Callback receiving aggregated progress of parallel transfers.
+pub type BatchProgressHandler = Box<dyn FnMut(&BatchProgress) + Send>;
+
+
+/// Pool of logged-in sessions running transfer jobs concurrently.
+///
+/// ```
+/// use std::sync::Arc;
+/// use ftp_cli::{ConnectionPool, SessionConfig, TransferJob};
+/// use ftp_cli::transport::TcpTransport;
+///
+/// # let server = ftp_cli::testing::TestServer::start(&[("/pub/a.txt", "a"), ("/pub/b.txt", "b")]);
+/// # let server = server.address();
+/// let mut pool = ConnectionPool::new(SessionConfig::new(server, "anonymous", "guest", Arc::new(TcpTransport::new(None))));
+/// let dir = std::env::temp_dir();
+/// let jobs = vec![
+///     TransferJob::download("a.txt", dir.join("a.txt").to_str().unwrap()),
+///     TransferJob::download("b.txt", dir.join("b.txt").to_str().unwrap()),
+/// ];
+/// for result in pool.run(2, "/pub", jobs, None) {
+///     if let Err(err) = result.result {
+///         panic!("{}: {}", result.job.remote_path, err);
+///     }
+/// }
+/// pool.close();
+/// ```
+pub struct ConnectionPool {
+    config: SessionConfig,
+    idle: Vec<FtpClient>,
+}
+
+impl ConnectionPool {
+
+    /// Creates empty pool. Sessions are opened on demand and kept for later runs.
+    pub fn new(config: SessionConfig) -> ConnectionPool {
+        ConnectionPool {
+            config,
+            idle: Vec::new(),
+        }
+    }
+
+    /// Settings used to open new sessions.
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// Name of user logged in by pool sessions.
+    pub fn user(&self) -> &str {
+        &self.config.user
+    }
+
+    /// Update speed limit of the whole pool. The limit is split evenly between running sessions.
+    pub fn set_rate_limit(&mut self, rate: Option<u64>) {
+        self.config.rate_limit = rate;
+    }
+
+    /// Runs all `jobs` using up to `workers` sessions, each starting in `remote_dir`.
+    /// Returns result of every job in the order jobs were given.
+    pub fn run(&mut self, workers: usize, remote_dir: &str, jobs: Vec<TransferJob>,
+               progress: Option<BatchProgressHandler>) -> Vec<JobResult> {
+        let workers = ::std::cmp::max(1, ::std::cmp::min(workers, jobs.len()));
+        let rate = self.config.rate_limit.map(|rate| ::std::cmp::max(1, rate / workers as u64));
+        let batch = Arc::new(Mutex::new(Batch::new(jobs.len(), progress)));
+        let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate().collect::<VecDeque<_>>()));
+        let results = Arc::new(Mutex::new(Vec::new()));
+
+        let mut sessions: Vec<Option<FtpClient>> = Vec::new();
+        for _ in 0..workers {
+            sessions.push(self.idle.pop());
+        }
+
+        let returned = thread::scope(|scope| {
+            let handles: Vec<_> = sessions.into_iter().enumerate().map(|(slot, session)| {
+                let worker = Worker {
+                    slot,
+                    config: &self.config,
+                    remote_dir,
+                    rate,
+                    batch: batch.clone(),
+                    queue: queue.clone(),
+                    results: results.clone(),
+                };
+                scope.spawn(move || worker.run(session))
+            }).collect();
+
+            handles.into_iter().filter_map(|h| h.join().ok().and_then(|s| s)).collect::<Vec<_>>()
+        });
+
+        for mut client in returned {
+            client.set_progress_handler(None);
+            client.set_rate_limit(self.config.rate_limit);
+            self.idle.push(client);
+        }
+
+        // Jobs left in queue when every session failed to connect
+        let mut results = results.lock().unwrap();
+        let mut batch = batch.lock().unwrap();
+        for (index, job) in queue.lock().unwrap().drain(..) {
+            batch.complete(None, false);
+            let error = batch.error.clone().unwrap_or_else(|| FtpError::OperationFailed("No connection available.".to_string()));
+            results.push((index, JobResult {
+                job,
+                bytes: 0,
+                duration: Duration::from_secs(0),
+                result: Err(error),
+            }));
+        }
+        batch.finish();
+
+        results.sort_by_key(|&(index, _)| index);
+        results.drain(..).map(|(_, result)| result).collect()
+    }
+
+    /// Download single file in `segments` byte ranges fetched in parallel by separate sessions.
+    /// Local file is preallocated and every session writes its range in place.
+    /// Returns size of the downloaded file.
+    pub fn get_segmented(&mut self, segments: usize, remote_dir: &str, remote_path: &str, local_path: &str,
+                         progress: Option<ProgressHandler>) -> Result<u64, FtpError> {
+        let mut first = match self.idle.pop() {
+            Some(client) => client,
+            None => self.config.open()?
+        };
+        let size = match first.cd(remote_dir).and_then(|_| first.size(remote_path)) {
+            Ok(size) => size,
+            Err(err) => {
+                self.idle.push(first);
+                return Err(err);
+            }
+        };
+
+        let file = File::create(local_path)?;
+        file.set_len(size)?;
+        drop(file);
+
+        let segments = ::std::cmp::max(1, ::std::cmp::min(segments as u64, size / MIN_SEGMENT_SIZE));
+        let segment_size = size.div_ceil(segments);
+        let rate = self.config.rate_limit.map(|rate| ::std::cmp::max(1, rate / segments));
+        let shared = Arc::new(Mutex::new(Segments::new(segments as usize, size, progress)));
+
+        let mut sessions = vec![Some(first)];
+        for _ in 1..segments {
+            sessions.push(self.idle.pop());
+        }
+
+        let config = &self.config;
+        let outcomes = thread::scope(|scope| {
+            let handles: Vec<_> = sessions.into_iter().enumerate().map(|(index, session)| {
+                let shared = shared.clone();
+                let offset = index as u64 * segment_size;
+                let length = ::std::cmp::min(segment_size, size - offset);
+                scope.spawn(move || {
+                    let mut client = match session {
+                        Some(client) => client,
+                        None => match config.open().and_then(|mut c| c.cd(remote_dir).map(|_| c)) {
+                            Ok(client) => client,
+                            Err(err) => return (None, Err(err))
+                        }
+                    };
+                    client.set_rate_limit(rate);
+                    client.set_progress_handler(Some(Box::new(move |p: &TransferProgress| {
+                        shared.lock().unwrap().update(index, p.transferred);
+                    })));
+
+                    let result = OpenOptions::new().write(true).open(local_path)
+                        .and_then(|mut file| file.seek(SeekFrom::Start(offset)).map(|_| file))
+                        .map_err(FtpError::from)
+                        .and_then(|mut file| client.get_range(remote_path, offset, length, &mut file))
+                        .and_then(|received| match received == length {
+                            true => Ok(received),
+                            false => Err(FtpError::OperationFailed(format!(
+                                "Segment at offset {} is incomplete: received {} of {} bytes.", offset, received, length)))
+                        });
+
+                    match result {
+                        Err(FtpError::IoError(_)) => (None, result),
+                        _ => (Some(client), result)
+                    }
+                })
+            }).collect();
+
+            handles.into_iter().filter_map(|h| h.join().ok()).collect::<Vec<_>>()
+        });
+
+        shared.lock().unwrap().finish();
+
+        let mut received = 0;
+        let mut error = None;
+        for (session, result) in outcomes {
+            if let Some(mut client) = session {
+                client.set_progress_handler(None);
+                client.set_rate_limit(self.config.rate_limit);
+                self.idle.push(client);
+            }
+            match result {
+                Ok(n) => received += n,
+                Err(err) => if error.is_none() { error = Some(err) }
+            }
+        }
+        if let Some(err) = error {
+            return Err(err);
+        }
+
+        let local_size = fs::metadata(local_path)?.len();
+        match received == size && local_size == size {
+            true => Ok(size),
+            false => Err(FtpError::OperationFailed(format!(
+                "Downloaded file size {} does not match remote size {}.", received, size)))
+        }
+    }
+
+    /// Close all idle sessions.
+    pub fn close(&mut self) {
+        for client in self.idle.drain(..) {
+            client.quit();
+        }
+    }
+}
+
+
+struct Worker<'a> {
+    slot: usize,
+    config: &'a SessionConfig,
+    remote_dir: &'a str,
+    rate: Option<u64>,
+    batch: Arc<Mutex<Batch>>,
+    queue: Arc<Mutex<VecDeque<(usize, TransferJob)>>>,
+    results: Arc<Mutex<Vec<(usize, JobResult)>>>,
+}
+
+impl<'a> Worker<'a> {
+
+    /// Processes queued jobs until queue is empty. Returns session if it is still usable.
+    fn run(self, session: Option<FtpClient>) -> Option<FtpClient> {
+        // Idle session from previous run may be in different directory
+        let mut session = session.and_then(|mut client| match client.cd(self.remote_dir) {
+            Ok(()) => {
+                client.set_rate_limit(self.rate);
+                Some(client)
+            }
+            Err(_) => None
+        });
+
+        loop {
+            let (index, job) = match self.queue.lock().unwrap().pop_front() {
+                Some(item) => item,
+                None => return session,
+            };
+
+            let mut client = match session.take() {
+                Some(client) => client,
+                None => match self.open() {
+                    Ok(client) => client,
+                    Err(err) => {
+                        // Leave the job for other workers and give up on this one
+                        self.queue.lock().unwrap().push_front((index, job));
+                        self.batch.lock().unwrap().error = Some(err);
+                        return None;
+                    }
+                }
+            };
+
+            let batch = self.batch.clone();
+            let slot = self.slot;
+            client.set_progress_handler(Some(Box::new(move |p: &TransferProgress| {
+                batch.lock().unwrap().update(slot, p.transferred);
+            })));
+
+            let start = Instant::now();
+            let result = job.run(&mut client);
+            let duration = start.elapsed();
+            let bytes = self.batch.lock().unwrap().complete(Some(slot), result.is_ok());
+
+            // Session is probably broken after communication error
+            session = match result {
+                Err(FtpError::IoError(_)) => None,
+                _ => Some(client)
+            };
+
+            self.results.lock().unwrap().push((index, JobResult { job, bytes, duration, result }));
+        }
+    }
+
+    fn open(&self) -> Result<FtpClient, FtpError> {
+        let mut client = self.config.open()?;
+        client.set_rate_limit(self.rate);
+        client.cd(self.remote_dir)?;
+        Ok(client)
+    }
+}
+
+
+/// Shared progress of segmented download.
+struct Segments {
+    handler: Option<ProgressHandler>,
+    start: Instant,
+    parts: Vec<u64>,
+    size: u64,
+}
+
+impl Segments {
+
+    fn new(count: usize, size: u64, handler: Option<ProgressHandler>) -> Segments {
+        let mut segments = Segments {
+            handler,
+            start: Instant::now(),
+            parts: vec![0; count],
+            size,
+        };
+        segments.send(false);
+        segments
+    }
+
+    fn update(&mut self, index: usize, transferred: u64) {
+        self.parts[index] = transferred;
+        self.send(false);
+    }
+
+    fn finish(&mut self) {
+        self.send(true);
+    }
+
+    fn send(&mut self, finished: bool) {
+        let progress = TransferProgress {
+            transferred: self.parts.iter().sum(),
+            total: Some(self.size),
+            elapsed: self.start.elapsed(),
+            finished,
+        };
+        if let Some(ref mut handler) = self.handler {
+            handler(&progress);
+        }
+    }
+}
+
+
+/// Shared state aggregating progress of running workers.
+struct Batch {
+    handler: Option<BatchProgressHandler>,
+    start: Instant,
+    files_total: usize,
+    files_done: usize,
+    files_failed: usize,
+    completed_bytes: u64,
+    running: Vec<u64>,
+    /// Why a worker couldn't open its session, reported for jobs which never started.
+    error: Option<FtpError>,
+}
+
+impl Batch {
+
+    fn new(files_total: usize, handler: Option<BatchProgressHandler>) -> Batch {
+        Batch {
+            handler,
+            start: Instant::now(),
+            files_total,
+            files_done: 0,
+            files_failed: 0,
+            completed_bytes: 0,
+            running: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn update(&mut self, slot: usize, transferred: u64) {
+        if self.running.len() <= slot {
+            self.running.resize(slot + 1, 0);
+        }
+        self.running[slot] = transferred;
+        self.send(false);
+    }
+
+    /// Mark job running in `slot` (or job which never started) as done. Returns bytes it transferred.
+    fn complete(&mut self, slot: Option<usize>, success: bool) -> u64 {
+        let bytes = match slot.and_then(|slot| self.running.get_mut(slot)) {
+            Some(bytes) => ::std::mem::replace(bytes, 0),
+            None => 0
+        };
+        self.completed_bytes += bytes;
+        match success {
+            true => self.files_done += 1,
+            false => self.files_failed += 1
+        }
+        self.send(false);
+        bytes
+    }
+
+    fn finish(&mut self) {
+        self.send(true);
+    }
+
+    fn send(&mut self, finished: bool) {
+        let progress = BatchProgress {
+            files_total: self.files_total,
+            files_done: self.files_done,
+            files_failed: self.files_failed,
+            transferred: self.completed_bytes + self.running.iter().sum::<u64>(),
+            elapsed: self.start.elapsed(),
+            finished,
+        };
+        if let Some(ref mut handler) = self.handler {
+            handler(&progress);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCategory;
+    use crate::testing::TestServer;
+    use crate::transport::TcpTransport;
+
+    fn config(server: &TestServer, password: &str) -> SessionConfig {
+        SessionConfig::new(server.address(), "user", password, Arc::new(TcpTransport::new(Some(Duration::from_secs(10)))))
+    }
+
+    #[test]
+    fn runs_jobs_in_parallel() {
+        let server = TestServer::start(&[("/pub/a.txt", "a"), ("/pub/b.txt", "bb"), ("/pub/c.txt", "ccc")]);
+        let root = ::std::env::temp_dir().join(format!("ftp-cli-pool-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let jobs = ["a.txt", "b.txt", "c.txt"].iter()
+            .map(|name| TransferJob::download(name, root.join(name).to_str().unwrap()))
+            .collect();
+
+        let mut pool = ConnectionPool::new(config(&server, "secret"));
+        let results = pool.run(2, "/pub", jobs, None);
+        pool.close();
+        let contents: Vec<_> = ["a.txt", "b.txt", "c.txt"].iter().map(|name| fs::read_to_string(root.join(name))).collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<_> = results.iter().map(|r| (r.job.remote_path.as_str(), r.result.is_ok(), r.bytes)).collect();
+        assert_eq!(names, vec![("a.txt", true, 1), ("b.txt", true, 2), ("c.txt", true, 3)]);
+        assert_eq!(contents.into_iter().map(Result::unwrap).collect::<Vec<_>>(), vec!["a", "bb", "ccc"]);
+    }
+
+    #[test]
+    fn refused_login_fails_jobs_as_auth_error() {
+        let server = TestServer::start(&[("/pub/a.txt", "a"), ("/pub/b.txt", "b"), ("/pub/c.txt", "c")]);
+        let jobs = vec![
+            TransferJob::download("a.txt", "never-written-a.txt"),
+            TransferJob::download("b.txt", "never-written-b.txt"),
+            TransferJob::download("c.txt", "never-written-c.txt"),
+        ];
+
+        let mut pool = ConnectionPool::new(config(&server, "wrong"));
+        let results = pool.run(2, "/pub", jobs, None);
+
+        assert_eq!(results.len(), 3);
+        for result in &results {
+            let category = result.result.as_ref().err().map(|err| err.category());
+            assert_eq!(category, Some(ErrorCategory::Auth), "{}", result.job.remote_path);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::Write;
+use std::time::Duration;
+
+use ftp_cli::pool::{BatchProgress, BatchProgressHandler};
+use ftp_cli::stream::{ProgressHandler, TransferProgress};
+
+const BAR_WIDTH: usize = 30;
+const REFRESH_INTERVAL_MS: u64 = 200;
+
+
+/// Creates progress handler rendering progress bar on stdout.
+pub fn progress_bar() -> ProgressHandler {
+    let mut bar = ProgressBar::new();
+    Box::new(move |progress| bar.update(progress))
+}
+
+
+/// Creates handler rendering aggregated progress of parallel transfers on stdout.
+pub fn batch_progress_bar() -> BatchProgressHandler {
+    let mut last_render = Duration::from_secs(0);
+    Box::new(move |progress: &BatchProgress| {
+        let since_last = progress.elapsed.checked_sub(last_render).unwrap_or_default();
+        if !progress.finished && since_last < Duration::from_millis(REFRESH_INTERVAL_MS) {
+            return;
+        }
+        last_render = progress.elapsed;
+
+        let secs = progress.elapsed.as_secs_f64();
+        let rate = match secs > 0.0 {
+            true => progress.transferred as f64 / secs,
+            false => 0.0
+        };
+        let done = progress.files_done + progress.files_failed;
+        let filled = match progress.files_total {
+            0 => BAR_WIDTH,
+            total => done * BAR_WIDTH / total
+        };
+        let failed = match progress.files_failed {
+            0 => String::new(),
+            n => format!(" ({} failed)", n)
+        };
+
+        let mut stdout = ::std::io::stdout();
+        let _ = write!(stdout, "\r[{}{}] {}/{} files{}  {}  {}/s  {}\x1b[K",
+            "#".repeat(filled), " ".repeat(BAR_WIDTH - filled), done, progress.files_total, failed,
+            format_bytes(progress.transferred), format_bytes(rate as u64), format_duration(secs));
+        if progress.finished {
+            let _ = writeln!(stdout);
+        }
+        let _ = stdout.flush();
+    })
+}
+
+
+/// Terminal progress bar showing transferred bytes, percentage, current rate and ETA.
+struct ProgressBar {
+    last_render: Duration,
+    last_transferred: u64,
+    rate: f64,
+}
+
+impl ProgressBar {
+
+    fn new() -> ProgressBar {
+        ProgressBar {
+            last_render: Duration::from_secs(0),
+            last_transferred: 0,
+            rate: 0.0,
+        }
+    }
+
+    fn update(&mut self, progress: &TransferProgress) {
+        if progress.transferred == 0 && !progress.finished {
+            // New transfer started
+            self.last_render = Duration::from_secs(0);
+            self.last_transferred = 0;
+            self.rate = 0.0;
+            return;
+        }
+
+        if progress.finished {
+            // Show average rate of the whole transfer
+            let secs = progress.elapsed.as_secs_f64();
+            if secs > 0.0 {
+                self.rate = progress.transferred as f64 / secs;
+            }
+        }
+        else {
+            let since_last = progress.elapsed.checked_sub(self.last_render).unwrap_or_default();
+            if since_last < Duration::from_millis(REFRESH_INTERVAL_MS) {
+                return;
+            }
+
+            // Exponential moving average smooths out bursts of network traffic
+            let current = (progress.transferred - self.last_transferred) as f64 / since_last.as_secs_f64();
+            self.rate = match self.rate > 0.0 {
+                true => 0.7 * self.rate + 0.3 * current,
+                false => current
+            };
+            self.last_render = progress.elapsed;
+            self.last_transferred = progress.transferred;
+        }
+
+        let mut stdout = ::std::io::stdout();
+        let _ = write!(stdout, "\r{}\x1b[K", self.render(progress));
+        if progress.finished {
+            let _ = writeln!(stdout);
+        }
+        let _ = stdout.flush();
+    }
+
+    fn render(&self, progress: &TransferProgress) -> String {
+        let rate = format!("{}/s", format_bytes(self.rate as u64));
+        match progress.total {
+            Some(total) if total > 0 => {
+                let ratio = (progress.transferred as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                let eta = match self.rate > 0.0 && !progress.finished {
+                    true => format_duration(total.saturating_sub(progress.transferred) as f64 / self.rate),
+                    false => format_duration(progress.elapsed.as_secs_f64())
+                };
+                format!("[{}{}] {:3}%  {} / {}  {}  {} {}",
+                    "#".repeat(filled), " ".repeat(BAR_WIDTH - filled), (ratio * 100.0) as u32,
+                    format_bytes(progress.transferred), format_bytes(total), rate,
+                    if progress.finished { "in" } else { "ETA" }, eta)
+            }
+            _ => format!("{}  {}", format_bytes(progress.transferred), rate)
+        }
+    }
+}
+
+
+/// Format number of bytes using binary units.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs >= 3600 {
+        true => format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+        false => format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs;
+use std::io::{BufRead, IsTerminal};
+use std::path::PathBuf;
+
+use rustyline::config::{Config, EditMode};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+
+use crate::completion::ShellHelper;
+
+const HISTORY_SIZE: usize = 1000;
+
+
+/// Source of REPL commands. Line editing with history is used when stdin is a terminal,
+/// commands piped from other programs are read line by line without any prompt.
+pub enum LineReader {
+    Interactive {
+        editor: Box<Editor<ShellHelper, DefaultHistory>>,
+        history: Option<PathBuf>,
+    },
+    Plain,
+}
+
+impl LineReader {
+
+    /// Creates reader with history persisted per `server` (`host:port`) and tab completion
+    /// provided by `helper`.
+    pub fn new(server: &str, vi_mode: bool, helper: ShellHelper) -> LineReader {
+        if !::std::io::stdin().is_terminal() {
+            return LineReader::Plain;
+        }
+
+        let builder = Config::builder()
+            .edit_mode(if vi_mode { EditMode::Vi } else { EditMode::Emacs })
+            .history_ignore_space(true);
+        let config = builder.max_history_size(HISTORY_SIZE)
+            .and_then(|b| b.history_ignore_dups(true))
+            .map(|b| b.build())
+            .unwrap_or_default();
+
+        let mut editor = match Editor::with_config(config) {
+            Ok(editor) => editor,
+            Err(_) => return LineReader::Plain
+        };
+        editor.set_helper(Some(helper));
+
+        let history = history_file(server);
+        if let Some(ref path) = history {
+            // Missing history file is normal on first connection
+            let _ = editor.load_history(path);
+        }
+
+        LineReader::Interactive {
+            editor: Box::new(editor),
+            history,
+        }
+    }
+
+    /// Update remote working directory used to complete relative paths.
+    pub fn set_remote_dir(&self, cwd: &str) {
+        if let Some(helper) = self.helper() {
+            helper.set_remote_dir(cwd);
+        }
+    }
+
+    /// Forget cached remote listings after remote files were changed.
+    pub fn invalidate_cache(&self) {
+        if let Some(helper) = self.helper() {
+            helper.invalidate();
+        }
+    }
+
+    fn helper(&self) -> Option<&ShellHelper> {
+        match *self {
+            LineReader::Interactive { ref editor, .. } => editor.helper(),
+            LineReader::Plain => None
+        }
+    }
+
+    /// Read next command. Returns `None` at the end of input.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        match *self {
+            LineReader::Interactive { ref mut editor, ref history } => {
+                loop {
+                    match editor.readline(prompt) {
+                        Ok(line) => {
+                            if !line.trim().is_empty() {
+                                let _ = editor.add_history_entry(line.as_str());
+                                if let Some(ref path) = *history {
+                                    let _ = editor.save_history(path);
+                                }
+                            }
+                            return Some(line);
+                        }
+                        // Ctrl-C discards current line like in shell
+                        Err(ReadlineError::Interrupted) => continue,
+                        Err(_) => return None
+                    }
+                }
+            }
+            LineReader::Plain => {
+                let mut line = String::new();
+                match ::std::io::stdin().lock().read_line(&mut line) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(line)
+                }
+            }
+        }
+    }
+}
+
+
+/// History file of given server in user's data directory.
+fn history_file(server: &str) -> Option<PathBuf> {
+    let dir = dirs::data_dir()?.join("ftp-cli").join("history");
+    fs::create_dir_all(&dir).ok()?;
+    let name: String = server.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    Some(dir.join(name))
+}
+
+
+/// Split command line to words like shell does. Words may be quoted with `'` or `"` and
+/// backslash escapes the next character (inside double quotes only `"` and `\`).
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Missing closing quote (').".to_string())
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("Missing closing quote (\").".to_string())
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("Missing closing quote (\").".to_string())
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => {
+                    in_word = true;
+                    word.push(c);
+                }
+                None => return Err("Missing character after \\.".to_string())
+            },
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(::std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Split text to commands separated by `;`. Separators quoted or escaped by backslash are kept.
+pub fn split_commands(text: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut command = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        if escaped {
+            escaped = false;
+        }
+        else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        }
+        else if Some(c) == quote {
+            quote = None;
+        }
+        else if quote.is_none() && (c == '\'' || c == '"') {
+            quote = Some(c);
+        }
+        else if quote.is_none() && c == ';' {
+            commands.push(::std::mem::take(&mut command));
+            continue;
+        }
+        command.push(c);
+    }
+
+    commands.push(command);
+    commands
+}
+
+/// Escape characters which have special meaning for `split_words`.
+pub fn escape_word(word: &str) -> String {
+    let mut escaped = String::with_capacity(word.len());
+    for c in word.chars() {
+        if c.is_whitespace() || c == '\'' || c == '"' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_words() {
+        let cases: &[(&str, &[&str])] = &[
+            ("get a b", &["get", "a", "b"]),
+            ("  get   a  ", &["get", "a"]),
+            ("", &[]),
+            ("put 'my file.txt'", &["put", "my file.txt"]),
+            ("put \"my file.txt\"", &["put", "my file.txt"]),
+            (r"a\ b c", &["a b", "c"]),
+            (r#""a\"b\\c""#, &[r#"a"b\c"#]),
+            (r#""a\nb""#, &[r"a\nb"]),
+            (r"'a\b'", &[r"a\b"]),
+            ("'' x", &["", "x"]),
+            (r#"x'y z'"w""#, &["xy zw"]),
+            ("žluťoučký kůň", &["žluťoučký", "kůň"]),
+        ];
+        for &(line, words) in cases {
+            assert_eq!(split_words(line).unwrap(), words, "{}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_unfinished_words() {
+        for line in ["get 'abc", "get \"abc", r#"get "abc\"#, r"get abc\"].iter() {
+            assert!(split_words(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn splits_commands() {
+        let cases: &[(&str, &[&str])] = &[
+            ("ls", &["ls"]),
+            ("", &[""]),
+            ("cd a; ls", &["cd a", " ls"]),
+            ("a;;b", &["a", "", "b"]),
+            ("get 'a;b'; ls", &["get 'a;b'", " ls"]),
+            ("get \"a;b\"", &["get \"a;b\""]),
+            (r"get a\;b;ls", &[r"get a\;b", "ls"]),
+            (r"get 'a\'; ls", &[r"get 'a\'", " ls"]),
+            (r#"get "a\";b"; ls"#, &[r#"get "a\";b""#, " ls"]),
+        ];
+        for &(text, commands) in cases {
+            assert_eq!(split_commands(text), commands, "{}", text);
+        }
+    }
+
+    #[test]
+    fn escapes_words() {
+        let cases = [
+            ("plain.txt", "plain.txt"),
+            ("my file", r"my\ file"),
+            ("it's", r"it\'s"),
+            (r#"a"b\c"#, r#"a\"b\\c"#),
+            ("tab\there", "tab\\\there"),
+            ("a;b", "a;b"),
+        ];
+        for &(word, escaped) in cases.iter() {
+            assert_eq!(escape_word(word), escaped, "{}", word);
+            assert_eq!(split_words(&escape_word(word)).unwrap(), [word], "{}", word);
+        }
+    }
+}
@@ -1,21 +1,27 @@
 use std::io::prelude::*;
 use std::io::Error as IoError;
+use std::thread;
+use std::time::{Duration, Instant};
 
 
 pub trait BufferedTransfer {
-    fn write_all_to<W: Write>(&mut self, ostream: &mut W, ) -> Result<(), IoError>;
+    /// Copies all data to `ostream` calling `progress` with total number of bytes written after each buffer.
+    fn write_all_to_with_progress<W: Write, F: FnMut(u64)>(&mut self, ostream: &mut W, progress: F) -> Result<(), IoError>;
 }
 
 
 impl<R: Read> BufferedTransfer for R {
 
-    fn write_all_to<W: Write>(&mut self, ostream: &mut W) -> Result<(), IoError> {
+    fn write_all_to_with_progress<W: Write, F: FnMut(u64)>(&mut self, ostream: &mut W, mut progress: F) -> Result<(), IoError> {
         let mut buf = vec![0; 4096];
         let mut done = false;
+        let mut total: u64 = 0;
         while !done {
-            let n = try!(self.read(&mut buf));
+            let n = self.read(&mut buf)?;
             if n > 0 {
-                try!(ostream.write_all(&buf[..n]))
+                ostream.write_all(&buf[..n])?;
+                total += n as u64;
+                progress(total);
             }
             else {
                 done = true;
@@ -25,3 +31,138 @@ impl<R: Read> BufferedTransfer for R {
         Ok(())
     }
 }
+
+
+/// State of running file transfer reported to progress handler.
+#[derive(Debug, Copy, Clone)]
+pub struct TransferProgress {
+    /// Number of bytes transferred so far.
+    pub transferred: u64,
+    /// Size of the whole file if known.
+    pub total: Option<u64>,
+    /// Time since the transfer started.
+    pub elapsed: Duration,
+    /// Set on the last report when transfer is complete.
+    pub finished: bool,
+}
+
+/// Callback receiving progress of `get` and `put` transfers.
+pub type ProgressHandler = Box<dyn FnMut(&TransferProgress) + Send>;
+
+
+/// Writer wrapper limiting throughput to given number of bytes per second.
+///
+/// Delay is computed from the total amount of data written since the transfer
+/// started, so the long-run average stays at the limit regardless of buffer size.
+pub struct Throttled<W: Write> {
+    inner: W,
+    rate: Option<u64>,
+    start: Instant,
+    written: u64,
+}
+
+impl<W: Write> Throttled<W> {
+
+    /// Wraps `inner` writer. No limit is applied when `rate` is `None`.
+    pub fn new(inner: W, rate: Option<u64>) -> Throttled<W> {
+        Throttled {
+            inner,
+            rate,
+            start: Instant::now(),
+            written: 0,
+        }
+    }
+
+    fn wait(&self) {
+        if let Some(rate) = self.rate {
+            let expected = Duration::from_secs_f64(self.written as f64 / rate as f64);
+            let elapsed = self.start.elapsed();
+            if expected > elapsed {
+                thread::sleep(expected - elapsed);
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Split writes so a single large buffer doesn't cause a long burst followed by long pause.
+        let max = match self.rate {
+            Some(rate) => ::std::cmp::max(1, ::std::cmp::min(buf.len() as u64, rate / 10)) as usize,
+            None => buf.len()
+        };
+        let n = self.inner.write(&buf[..max])?;
+        self.written += n as u64;
+        self.wait();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.inner.flush()
+    }
+}
+
+
+/// Parse transfer rate like `512`, `100k` or `2M` (bytes per second).
+/// Returns `Ok(None)` for `off` or `0` meaning unlimited.
+pub fn parse_rate(text: &str) -> Result<Option<u64>, String> {
+    let text = text.trim();
+    if text == "off" || text == "unlimited" {
+        return Ok(None);
+    }
+
+    let (num, multiplier) = match text.chars().last() {
+        Some('k') | Some('K') => (&text[..text.len()-1], 1024),
+        Some('m') | Some('M') => (&text[..text.len()-1], 1024 * 1024),
+        Some('g') | Some('G') => (&text[..text.len()-1], 1024 * 1024 * 1024),
+        _ => (text, 1)
+    };
+
+    match num.parse::<u64>().map(|n| n.checked_mul(multiplier)) {
+        Ok(Some(0)) => Ok(None),
+        Ok(Some(rate)) => Ok(Some(rate)),
+        _ => Err(format!("Invalid rate \"{}\". Expected number of bytes per second (e.g. 512, 100k, 2M) or \"off\".", text))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        let cases = [
+            ("512", Some(512)),
+            ("100k", Some(100 * 1024)),
+            ("2M", Some(2 * 1024 * 1024)),
+            ("1g", Some(1024 * 1024 * 1024)),
+            ("0", None),
+            ("off", None),
+            ("unlimited", None),
+        ];
+        for &(text, expected) in cases.iter() {
+            assert_eq!(parse_rate(text), Ok(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        for text in ["", "k", "fast", "-5", "1.5M", "99999999999G", "18446744073709551616"].iter() {
+            assert!(parse_rate(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn throttled_accepts_empty_buffer() {
+        let mut out = Throttled::new(Vec::new(), Some(100));
+        assert_eq!(out.write(&[]).unwrap(), 0);
+        out.write_all(b"abc").unwrap();
+        assert_eq!(out.inner, b"abc");
+    }
+}
@@ -0,0 +1,841 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::FtpError;
+use crate::ftp_client::FtpClient;
+use crate::mirror::{local_state, remote_state, set_modified, FileState};
+use crate::pool::{Direction, JobResult, TransferJob};
+use crate::tree::join_remote;
+
+/// Name of file in local root keeping state of last synchronization.
+pub const STATE_FILE: &str = ".ftp-cli-sync";
+
+const STATE_HEADER: &str = "# ftp-cli sync state v1";
+
+
+/// How to resolve files changed on both sides since last synchronization.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConflictPolicy {
+    /// Version with newer modification time overwrites the other one.
+    NewerWins,
+    /// Local version is renamed and both versions are kept on both sides.
+    KeepBoth,
+    /// Nothing is synchronized when any conflict is found.
+    Abort,
+}
+
+impl ConflictPolicy {
+
+    pub fn parse(text: &str) -> Option<ConflictPolicy> {
+        match text {
+            "newer" | "newer-wins" => Some(ConflictPolicy::NewerWins),
+            "keep-both" | "both" => Some(ConflictPolicy::KeepBoth),
+            "abort" => Some(ConflictPolicy::Abort),
+            _ => None
+        }
+    }
+}
+
+
+/// State of path recorded after last successful synchronization.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncRecord {
+    pub is_dir: bool,
+    pub local_size: u64,
+    pub local_modified: Option<i64>,
+    pub remote_size: u64,
+    pub remote_modified: Option<i64>,
+    /// Hash of local file content, detects files touched without changing them.
+    pub hash: Option<u64>,
+}
+
+/// Persistent synchronization state stored in local root directory.
+#[derive(Debug, Default)]
+pub struct SyncState {
+    pub records: BTreeMap<String, SyncRecord>,
+}
+
+impl SyncState {
+
+    /// Load state of local root. Missing file means first synchronization.
+    pub fn load(local_root: &Path) -> Result<SyncState, FtpError> {
+        let mut state = SyncState::default();
+        let file = match File::open(local_root.join(STATE_FILE)) {
+            Ok(file) => file,
+            Err(_) => return Ok(state)
+        };
+
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if number == 0 && line != STATE_HEADER {
+                return Err(FtpError::OperationFailed(format!("Unsupported sync state file {}.", STATE_FILE)));
+            }
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                return Err(FtpError::OperationFailed(format!("Invalid line {} in sync state file.", number + 1)));
+            }
+            state.records.insert(unescape(fields[0]), SyncRecord {
+                is_dir: fields[1] == "d",
+                local_size: fields[2].parse().unwrap_or(0),
+                local_modified: fields[3].parse().ok(),
+                remote_size: fields[4].parse().unwrap_or(0),
+                remote_modified: fields[5].parse().ok(),
+                hash: u64::from_str_radix(fields[6], 16).ok(),
+            });
+        }
+        Ok(state)
+    }
+
+    /// Write state to local root, replacing previous one.
+    pub fn save(&self, local_root: &Path) -> Result<(), FtpError> {
+        let path = local_root.join(STATE_FILE);
+        let temp = local_root.join(format!("{}.tmp", STATE_FILE));
+        {
+            let mut file = File::create(&temp)?;
+            writeln!(file, "{}", STATE_HEADER)?;
+            for (path, record) in &self.records {
+                let time = |t: Option<i64>| t.map(|t| t.to_string()).unwrap_or_else(|| "-".to_string());
+                writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    escape(path), if record.is_dir { "d" } else { "f" },
+                    record.local_size, time(record.local_modified),
+                    record.remote_size, time(record.remote_modified),
+                    record.hash.map(|h| format!("{:016x}", h)).unwrap_or_else(|| "-".to_string()))?;
+            }
+        }
+        fs::rename(&temp, &path)?;
+        Ok(())
+    }
+}
+
+
+/// Change of single path since last synchronization.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Change {
+    None,
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// Single step of synchronization.
+#[derive(Debug, Clone)]
+pub enum SyncAction {
+    CreateLocalDir(String),
+    CreateRemoteDir(String),
+    Download(String),
+    Upload(String),
+    DeleteLocal(String, bool),
+    DeleteRemote(String, bool),
+    /// Keep local version of conflicting file under new name on both sides.
+    KeepLocalCopy(String, String),
+}
+
+impl Display for SyncAction {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        match *self {
+            SyncAction::CreateLocalDir(ref path) => write!(f, "local mkdir   {}", path),
+            SyncAction::CreateRemoteDir(ref path) => write!(f, "remote mkdir  {}", path),
+            SyncAction::Download(ref path) => write!(f, "get           {}", path),
+            SyncAction::Upload(ref path) => write!(f, "put           {}", path),
+            SyncAction::DeleteLocal(ref path, _) => write!(f, "local rm      {}", path),
+            SyncAction::DeleteRemote(ref path, _) => write!(f, "remote rm     {}", path),
+            SyncAction::KeepLocalCopy(ref path, ref copy) => write!(f, "keep both     {} (local copy as {})", path, copy),
+        }
+    }
+}
+
+/// Planned synchronization.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    /// Paths changed on both sides.
+    pub conflicts: Vec<String>,
+}
+
+
+/// Compare both sides with last synchronization state and plan actions.
+pub fn plan(client: &mut FtpClient, local_root: &Path, remote_root: &str, policy: ConflictPolicy) -> Result<SyncPlan, FtpError> {
+    let state = SyncState::load(local_root)?;
+    let mut local = local_state(local_root)?;
+    local.remove(STATE_FILE);
+    local.remove(&format!("{}.tmp", STATE_FILE));
+    let remote = remote_state(client, remote_root)?;
+
+    let mut paths = BTreeSet::new();
+    paths.extend(local.keys().cloned());
+    paths.extend(remote.keys().cloned());
+    paths.extend(state.records.keys().cloned());
+
+    let changes: BTreeMap<String, (Change, Change)> = paths.into_iter()
+        .map(|path| {
+            let record = state.records.get(&path);
+            let changes = (local_change(local_root, &path, local.get(&path), record),
+                           remote_change(remote.get(&path), record));
+            (path, changes)
+        })
+        .collect();
+
+    let mut plan = SyncPlan::default();
+    let mut deletes = Vec::new();
+
+    for (path, &(local_change, remote_change)) in &changes {
+        let path = path.clone();
+        let l = local.get(&path);
+        let r = remote.get(&path);
+
+        match (local_change, remote_change) {
+            (Change::None, Change::None) | (Change::Deleted, Change::Deleted) => { }
+
+            // Directory is deleted only with all its content recorded and unchanged,
+            // otherwise it's restored on the other side to keep the changed entries
+            (Change::None, Change::Deleted) if is_dir(l) && !unchanged_under(&changes, &local, &path, |c| c.0) => {
+                plan.actions.push(upload(&path, l));
+            }
+            (Change::Deleted, Change::None) if is_dir(r) && !unchanged_under(&changes, &remote, &path, |c| c.1) => {
+                plan.actions.push(download(&path, r));
+            }
+            (Change::None, Change::Deleted) => deletes.push(SyncAction::DeleteLocal(path, is_dir(l))),
+            (Change::Deleted, Change::None) => deletes.push(SyncAction::DeleteRemote(path, is_dir(r))),
+            (Change::Created, Change::None) | (Change::Modified, Change::None) => plan.actions.push(upload(&path, l)),
+            (Change::None, Change::Created) | (Change::None, Change::Modified) => plan.actions.push(download(&path, r)),
+
+            // Directories exist on both sides, nothing to merge
+            _ if is_dir(l) && is_dir(r) => { }
+            // First synchronization of identical file
+            (Change::Created, Change::Created) if same_file(l, r) => { }
+
+            (Change::Deleted, _) => {
+                // Remote was changed after local delete, restore it
+                plan.actions.push(download(&path, r));
+            }
+            (_, Change::Deleted) => {
+                plan.actions.push(upload(&path, l));
+            }
+            _ => {
+                plan.conflicts.push(path.clone());
+                match policy {
+                    ConflictPolicy::Abort => { }
+                    ConflictPolicy::NewerWins => {
+                        let local_time = l.and_then(|s| s.modified).unwrap_or(0);
+                        let remote_time = r.and_then(|s| s.modified).unwrap_or(0);
+                        match local_time > remote_time {
+                            true => plan.actions.push(upload(&path, l)),
+                            false => plan.actions.push(download(&path, r))
+                        }
+                    }
+                    ConflictPolicy::KeepBoth => {
+                        let copy = conflict_name(&path);
+                        plan.actions.push(SyncAction::KeepLocalCopy(path.clone(), copy));
+                        plan.actions.push(download(&path, r));
+                    }
+                }
+            }
+        }
+    }
+
+    // Children are deleted before their parents
+    deletes.reverse();
+    plan.actions.append(&mut deletes);
+    Ok(plan)
+}
+
+/// Whether every entry under directory `dir` present on one side is unchanged since last
+/// synchronization. `change` picks change of that side.
+fn unchanged_under<F>(changes: &BTreeMap<String, (Change, Change)>, side: &BTreeMap<String, FileState>, dir: &str, change: F) -> bool
+    where F: Fn(&(Change, Change)) -> Change
+{
+    let prefix = format!("{}/", dir);
+    changes.range(prefix.clone()..)
+        .take_while(|&(path, _)| path.starts_with(&prefix))
+        .all(|(path, changes)| !side.contains_key(path) || change(changes) == Change::None)
+}
+
+fn local_change(local_root: &Path, path: &str, current: Option<&FileState>, record: Option<&SyncRecord>) -> Change {
+    match (current, record) {
+        (None, None) => Change::None,
+        (Some(_), None) => Change::Created,
+        (None, Some(_)) => Change::Deleted,
+        (Some(current), Some(record)) => {
+            if current.is_dir != record.is_dir || (!current.is_dir && current.size != record.local_size) {
+                Change::Modified
+            }
+            else if current.is_dir || current.modified == record.local_modified {
+                Change::None
+            }
+            else {
+                // Touched file is not a change unless its content differs
+                match hash_file(&local_root.join(path)).ok() == record.hash {
+                    true => Change::None,
+                    false => Change::Modified
+                }
+            }
+        }
+    }
+}
+
+fn remote_change(current: Option<&FileState>, record: Option<&SyncRecord>) -> Change {
+    match (current, record) {
+        (None, None) => Change::None,
+        (Some(_), None) => Change::Created,
+        (None, Some(_)) => Change::Deleted,
+        (Some(current), Some(record)) => {
+            let changed = current.is_dir != record.is_dir
+                || (!current.is_dir && (current.size != record.remote_size || current.modified != record.remote_modified));
+            match changed {
+                true => Change::Modified,
+                false => Change::None
+            }
+        }
+    }
+}
+
+fn is_dir(state: Option<&FileState>) -> bool {
+    state.map(|s| s.is_dir).unwrap_or(false)
+}
+
+fn same_file(local: Option<&FileState>, remote: Option<&FileState>) -> bool {
+    match (local, remote) {
+        (Some(l), Some(r)) => !l.is_dir && !r.is_dir && l.size == r.size,
+        _ => false
+    }
+}
+
+fn upload(path: &str, state: Option<&FileState>) -> SyncAction {
+    match is_dir(state) {
+        true => SyncAction::CreateRemoteDir(path.to_string()),
+        false => SyncAction::Upload(path.to_string())
+    }
+}
+
+fn download(path: &str, state: Option<&FileState>) -> SyncAction {
+    match is_dir(state) {
+        true => SyncAction::CreateLocalDir(path.to_string()),
+        false => SyncAction::Download(path.to_string())
+    }
+}
+
+/// Name for local version of conflicting file: `dir/name.local-conflict.ext`.
+fn conflict_name(path: &str) -> String {
+    let (dir, name) = match path.rfind('/') {
+        Some(pos) => (&path[..pos+1], &path[pos+1..]),
+        None => ("", path)
+    };
+    match name.rfind('.') {
+        Some(pos) if pos > 0 => format!("{}{}.local-conflict{}", dir, &name[..pos], &name[pos..]),
+        _ => format!("{}{}.local-conflict", dir, name)
+    }
+}
+
+
+/// Summary of executed synchronization.
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    /// Created and deleted directories and files.
+    pub actions: usize,
+    pub failed: usize,
+}
+
+/// Execute planned actions and save new synchronization state.
+/// File transfers are handed over to `transfer` in one batch so they can run in parallel.
+pub fn execute<T, R>(client: &mut FtpClient, local_root: &Path, remote_root: &str, plan: SyncPlan,
+                     transfer: T, mut report: R) -> Result<SyncSummary, FtpError>
+    where T: FnOnce(&mut FtpClient, Vec<TransferJob>) -> Vec<JobResult>,
+          R: FnMut(&str, FtpError)
+{
+    let previous = SyncState::load(local_root)?;
+    let mut summary = SyncSummary::default();
+    let mut failed = BTreeSet::new();
+    // Paths with other than delete action, directories containing them are kept
+    let mut touched = BTreeSet::new();
+    let mut jobs = Vec::new();
+    let mut deletes = Vec::new();
+
+    let local_path = |path: &str| -> PathBuf { local_root.join(path) };
+    let remote_path = |path: &str| join_remote(remote_root, path);
+
+    for action in plan.actions {
+        match action {
+            SyncAction::DeleteLocal(..) | SyncAction::DeleteRemote(..) => { }
+            SyncAction::KeepLocalCopy(ref path, ref copy) => touched.extend(vec![path.clone(), copy.clone()]),
+            SyncAction::CreateLocalDir(ref path) | SyncAction::CreateRemoteDir(ref path) |
+            SyncAction::Download(ref path) | SyncAction::Upload(ref path) => { touched.insert(path.clone()); }
+        }
+        let (path, result) = match action {
+            SyncAction::CreateLocalDir(path) => {
+                let r = fs::create_dir_all(local_path(&path)).map_err(FtpError::from);
+                (path, r)
+            }
+            SyncAction::CreateRemoteDir(path) => {
+                let r = client.mkdir(&remote_path(&path));
+                (path, r)
+            }
+            SyncAction::Download(path) => {
+                jobs.push(TransferJob::download(&remote_path(&path), &local_path(&path).to_string_lossy()));
+                continue;
+            }
+            SyncAction::Upload(path) => {
+                jobs.push(TransferJob::upload(&local_path(&path).to_string_lossy(), &remote_path(&path)));
+                continue;
+            }
+            SyncAction::KeepLocalCopy(path, copy) => {
+                let r = fs::rename(local_path(&path), local_path(&copy)).map_err(FtpError::from);
+                if r.is_ok() {
+                    jobs.push(TransferJob::upload(&local_path(&copy).to_string_lossy(), &remote_path(&copy)));
+                }
+                (path, r)
+            }
+            delete => {
+                deletes.push(delete);
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => summary.actions += 1,
+            Err(e) => {
+                report(&path, e);
+                summary.failed += 1;
+                failed.insert(path);
+            }
+        }
+    }
+
+    // Downloaded files get remote modification time
+    let mut times = Vec::new();
+    for job in &jobs {
+        times.push(match job.direction {
+            Direction::Download => client.modified(&job.remote_path).ok(),
+            Direction::Upload => None
+        });
+    }
+
+    for (index, result) in transfer(client, jobs).into_iter().enumerate() {
+        let path = relative(&result.job.remote_path, remote_root);
+        match result.result {
+            Ok(()) => {
+                match result.job.direction {
+                    Direction::Download => summary.downloaded += 1,
+                    Direction::Upload => summary.uploaded += 1
+                }
+                if let Some(&Some(time)) = times.get(index) {
+                    let _ = set_modified(Path::new(&result.job.local_path), time);
+                }
+            }
+            Err(_) => {
+                summary.failed += 1;
+                failed.insert(path);
+            }
+        }
+    }
+
+    // Planned in reverse order, so children are deleted before their directory
+    for action in deletes {
+        match action {
+            SyncAction::DeleteLocal(ref path, true) | SyncAction::DeleteRemote(ref path, true)
+                if contains_under(&touched, path) || contains_under(&failed, path) => continue,
+            _ => { }
+        }
+        let (path, result) = match action {
+            SyncAction::DeleteLocal(path, true) => { let r = fs::remove_dir(local_path(&path)).map_err(FtpError::from); (path, r) }
+            SyncAction::DeleteLocal(path, false) => { let r = fs::remove_file(local_path(&path)).map_err(FtpError::from); (path, r) }
+            SyncAction::DeleteRemote(path, true) => { let r = client.rmdir(&remote_path(&path)); (path, r) }
+            SyncAction::DeleteRemote(path, false) => { let r = client.delete(&remote_path(&path)); (path, r) }
+            _ => continue
+        };
+        match result {
+            Ok(()) => summary.actions += 1,
+            Err(e) => {
+                report(&path, e);
+                summary.failed += 1;
+                failed.insert(path);
+            }
+        }
+    }
+
+    // Record what is on both sides now, failed paths keep their previous state to be retried next time
+    let mut local = local_state(local_root)?;
+    local.remove(STATE_FILE);
+    let remote = remote_state(client, remote_root)?;
+    let mut state = SyncState::default();
+    for (path, l) in &local {
+        if failed.contains(path) {
+            continue;
+        }
+        if let Some(r) = remote.get(path) {
+            let hash = match l.is_dir {
+                true => None,
+                false => hash_file(&local_path(path)).ok()
+            };
+            state.records.insert(path.clone(), SyncRecord {
+                is_dir: l.is_dir,
+                local_size: l.size,
+                local_modified: l.modified,
+                remote_size: r.size,
+                remote_modified: r.modified,
+                hash,
+            });
+        }
+    }
+    for path in &failed {
+        if let Some(record) = previous.records.get(path) {
+            state.records.insert(path.clone(), record.clone());
+        }
+    }
+    state.save(local_root)?;
+
+    Ok(summary)
+}
+
+/// Whether `paths` contain any entry under directory `dir`.
+fn contains_under(paths: &BTreeSet<String>, dir: &str) -> bool {
+    let prefix = format!("{}/", dir);
+    paths.range(prefix.clone()..).next().is_some_and(|path| path.starts_with(&prefix))
+}
+
+fn relative(path: &str, root: &str) -> String {
+    match path.strip_prefix(root) {
+        Some(rest) => rest.trim_start_matches('/').to_string(),
+        None => path.to_string()
+    }
+}
+
+
+/// FNV-1a hash of file content.
+pub fn hash_file(path: &Path) -> Result<u64, FtpError> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut hash: u64 = 0xcbf29ce484222325;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        for &byte in &buf[..n] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Escape characters which can't appear in tab separated state line.
+fn escape(path: &str) -> String {
+    path.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\')
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::testing::TestServer;
+
+    /// Modification time of every file on `TestServer`.
+    const REMOTE_TIME: i64 = 1704164645;
+    const LOCAL_TIME: i64 = REMOTE_TIME + 100;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("ftp-cli-sync-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        set_modified(&path, LOCAL_TIME).unwrap();
+    }
+
+    fn file(size: u64, modified: i64) -> FileState {
+        FileState { is_dir: false, size, modified: Some(modified) }
+    }
+
+    fn dir() -> FileState {
+        FileState { is_dir: true, size: 4096, modified: Some(LOCAL_TIME) }
+    }
+
+    /// Record of file synchronized with given local and remote content.
+    fn synced(root: &Path, local: &str, remote: &str) -> SyncRecord {
+        let temp = root.join("hashed");
+        fs::write(&temp, local).unwrap();
+        let hash = hash_file(&temp).ok();
+        fs::remove_file(&temp).unwrap();
+        SyncRecord {
+            is_dir: false,
+            local_size: local.len() as u64,
+            local_modified: Some(LOCAL_TIME),
+            remote_size: remote.len() as u64,
+            remote_modified: Some(REMOTE_TIME),
+            hash,
+        }
+    }
+
+    fn synced_dir() -> SyncRecord {
+        SyncRecord { is_dir: true, local_size: 4096, local_modified: None, remote_size: 0, remote_modified: None, hash: None }
+    }
+
+    fn plan_of(server: &TestServer, root: &Path, policy: ConflictPolicy) -> (Vec<String>, Vec<String>) {
+        let mut client = FtpClient::connect(server.address(), Some(Duration::from_secs(10)), None).unwrap();
+        client.login("anonymous", "guest", None).unwrap();
+        let plan = plan(&mut client, root, "/sync", policy).unwrap();
+        (plan.actions.iter().map(|action| action.to_string()).collect(), plan.conflicts)
+    }
+
+    #[test]
+    fn names_conflict_copy() {
+        let cases = [
+            ("report.csv", "report.local-conflict.csv"),
+            ("dir/archive.tar.gz", "dir/archive.tar.local-conflict.gz"),
+            ("dir.d/README", "dir.d/README.local-conflict"),
+            (".profile", ".profile.local-conflict"),
+            ("a/b/.profile", "a/b/.profile.local-conflict"),
+        ];
+        for &(path, copy) in cases.iter() {
+            assert_eq!(conflict_name(path), copy);
+        }
+    }
+
+    #[test]
+    fn escapes_paths() {
+        let cases = [
+            ("plain/path.txt", "plain/path.txt"),
+            ("tab\there", "tab\\there"),
+            ("new\nline", "new\\nline"),
+            ("back\\slash\\t", "back\\\\slash\\\\t"),
+            ("", ""),
+        ];
+        for &(path, escaped) in cases.iter() {
+            assert_eq!(escape(path), escaped);
+            assert_eq!(unescape(&escape(path)), path);
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_state() {
+        let root = temp_dir("state");
+        let empty = SyncState::load(&root).unwrap();
+
+        let mut state = SyncState::default();
+        state.records.insert("dir".to_string(), synced_dir());
+        state.records.insert("dir/a\tb\\n.txt".to_string(), synced(&root, "local", "remote"));
+        state.records.insert("no times".to_string(), SyncRecord {
+            is_dir: false, local_size: 1, local_modified: None, remote_size: 2, remote_modified: None, hash: None
+        });
+        state.save(&root).unwrap();
+        let loaded = SyncState::load(&root).unwrap();
+        let temp_left = root.join(format!("{}.tmp", STATE_FILE)).exists();
+
+        fs::write(root.join(STATE_FILE), "# other format\n").unwrap();
+        let unsupported = SyncState::load(&root);
+        fs::write(root.join(STATE_FILE), format!("{}\npath\tf\t1\n", STATE_HEADER)).unwrap();
+        let invalid = SyncState::load(&root);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(empty.records.is_empty());
+        assert_eq!(loaded.records, state.records);
+        assert!(!temp_left);
+        assert!(unsupported.is_err());
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn detects_changes_against_state() {
+        let root = temp_dir("changes");
+        write(&root, "same", "content");
+        write(&root, "other", "CONTENT");
+        let record = synced(&root, "content", "remote");
+        let dir_record = synced_dir();
+
+        let local = |path: &str, current: Option<FileState>, record: Option<&SyncRecord>| {
+            local_change(&root, path, current.as_ref(), record)
+        };
+        let local_cases = [
+            (local("same", None, None), Change::None),
+            (local("same", Some(file(7, LOCAL_TIME)), None), Change::Created),
+            (local("same", None, Some(&record)), Change::Deleted),
+            (local("same", Some(file(7, LOCAL_TIME)), Some(&record)), Change::None),
+            (local("same", Some(file(8, LOCAL_TIME)), Some(&record)), Change::Modified),
+            (local("same", Some(dir()), Some(&record)), Change::Modified),
+            (local("same", Some(file(7, LOCAL_TIME)), Some(&dir_record)), Change::Modified),
+            (local("same", Some(dir()), Some(&dir_record)), Change::None),
+            // Touched file is compared by content
+            (local("same", Some(file(7, LOCAL_TIME + 1)), Some(&record)), Change::None),
+            (local("other", Some(file(7, LOCAL_TIME + 1)), Some(&record)), Change::Modified),
+        ];
+        let remote_cases = [
+            (remote_change(None, None), Change::None),
+            (remote_change(Some(&file(6, REMOTE_TIME)), None), Change::Created),
+            (remote_change(None, Some(&record)), Change::Deleted),
+            (remote_change(Some(&file(6, REMOTE_TIME)), Some(&record)), Change::None),
+            (remote_change(Some(&file(7, REMOTE_TIME)), Some(&record)), Change::Modified),
+            (remote_change(Some(&file(6, REMOTE_TIME + 1)), Some(&record)), Change::Modified),
+            (remote_change(Some(&dir()), Some(&record)), Change::Modified),
+            (remote_change(Some(&dir()), Some(&dir_record)), Change::None),
+        ];
+        fs::remove_dir_all(&root).unwrap();
+
+        for (index, &(change, expected)) in local_cases.iter().enumerate() {
+            assert_eq!(change, expected, "local case {}", index);
+        }
+        for (index, &(change, expected)) in remote_cases.iter().enumerate() {
+            assert_eq!(change, expected, "remote case {}", index);
+        }
+    }
+
+    #[test]
+    fn plans_every_change_pair() {
+        let server = TestServer::start(&[
+            ("/sync/a", "a"),
+            ("/sync/b", "b"),
+            ("/sync/c", "cc"),
+            ("/sync/f", "same"),
+            ("/sync/g", "remote!"),
+            ("/sync/h", "h"),
+            ("/sync/i", "remote"),
+            ("/sync/k", "k"),
+            ("/sync/l", "ll"),
+            ("/sync/n", "n"),
+            ("/sync/r/f", "f"),
+            ("/sync/s/changed", "changed"),
+            ("/sync/s/old", "o"),
+        ]);
+        let root = temp_dir("plan");
+        let mut state = SyncState::default();
+        for &(path, local, remote) in [("a", "a", "a"), ("c", "c", "c"), ("d", "d", "d"), ("h", "h", "h"),
+                                       ("i", "i", "i"), ("j", "j", "j"), ("k", "k", "k"), ("l", "l", "l"),
+                                       ("m", "m", "m"), ("n", "n", "n"), ("p/f", "f", "f"), ("q/old", "o", "o"),
+                                       ("r/f", "f", "f"), ("s/changed", "c", "c"), ("s/old", "o", "o")].iter() {
+            state.records.insert(path.to_string(), synced(&root, local, remote));
+        }
+        for &path in ["p", "q", "r", "s"].iter() {
+            state.records.insert(path.to_string(), synced_dir());
+        }
+        state.save(&root).unwrap();
+        for &(path, content) in [("a", "a"), ("c", "c"), ("d", "d"), ("e", "e"), ("f", "same"), ("g", "local"),
+                                 ("h", "hh"), ("i", "ii"), ("j", "jj"), ("n", "n"), ("p/f", "f"),
+                                 ("q/old", "o"), ("q/new", "new")].iter() {
+            write(&root, path, content);
+        }
+        set_modified(&root.join("n"), LOCAL_TIME + 10).unwrap();
+
+        let (actions, conflicts) = plan_of(&server, &root, ConflictPolicy::Abort);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(actions, vec![
+            "get           b",                  // (None, Created)
+            "get           c",                  // (None, Modified)
+            "put           e",                  // (Created, None)
+            "put           h",                  // (Modified, None)
+            "put           j",                  // (Modified, Deleted)
+            "get           l",                  // (Deleted, Modified)
+            // Directory deleted remotely keeps new local content
+            "remote mkdir  q",
+            "put           q/new",
+            // Directory deleted locally keeps changed remote content
+            "local mkdir   s",
+            "get           s/changed",
+            // Deletes go last, children before their directories
+            "remote rm     s/old",
+            "remote rm     r/f",
+            "remote rm     r",
+            "local rm      q/old",
+            "local rm      p/f",
+            "local rm      p",
+            "remote rm     k",                  // (Deleted, None)
+            "local rm      d",                  // (None, Deleted)
+        ]);
+        assert_eq!(conflicts, vec!["g", "i"]);
+    }
+
+    #[test]
+    fn resolves_conflicts_by_policy() {
+        let server = TestServer::start(&[("/sync/dir/i.txt", "remote")]);
+        let root = temp_dir("conflict");
+        let mut state = SyncState::default();
+        state.records.insert("dir".to_string(), synced_dir());
+        state.records.insert("dir/i.txt".to_string(), synced(&root, "i", "i"));
+        state.save(&root).unwrap();
+        write(&root, "dir/i.txt", "local");
+
+        let newer_local = plan_of(&server, &root, ConflictPolicy::NewerWins);
+        set_modified(&root.join("dir/i.txt"), REMOTE_TIME - 1).unwrap();
+        let newer_remote = plan_of(&server, &root, ConflictPolicy::NewerWins);
+        let keep_both = plan_of(&server, &root, ConflictPolicy::KeepBoth);
+        let abort = plan_of(&server, &root, ConflictPolicy::Abort);
+        fs::remove_dir_all(&root).unwrap();
+
+        let conflicts = vec!["dir/i.txt".to_string()];
+        assert_eq!(newer_local, (vec!["put           dir/i.txt".to_string()], conflicts.clone()));
+        assert_eq!(newer_remote, (vec!["get           dir/i.txt".to_string()], conflicts.clone()));
+        assert_eq!(keep_both, (vec![
+            "keep both     dir/i.txt (local copy as dir/i.local-conflict.txt)".to_string(),
+            "get           dir/i.txt".to_string(),
+        ], conflicts.clone()));
+        assert_eq!(abort, (vec![], conflicts));
+    }
+
+    #[test]
+    fn deletes_only_recorded_entries() {
+        let server = TestServer::start(&[("/sync/q/x", "x")]);
+        let root = temp_dir("execute");
+        for &path in ["p/f", "p/late", "q/y", "t/kept"].iter() {
+            write(&root, path, path);
+        }
+        let plan = SyncPlan {
+            actions: vec![
+                SyncAction::Download("q/x".to_string()),
+                SyncAction::DeleteLocal("p/f".to_string(), false),
+                SyncAction::DeleteLocal("p".to_string(), true),
+                SyncAction::DeleteLocal("t/missing".to_string(), false),
+                SyncAction::DeleteLocal("t".to_string(), true),
+                SyncAction::DeleteLocal("q".to_string(), true),
+            ],
+            conflicts: vec![],
+        };
+
+        let mut client = FtpClient::connect(server.address(), Some(Duration::from_secs(10)), None).unwrap();
+        client.login("anonymous", "guest", None).unwrap();
+        let mut reported = Vec::new();
+        let summary = execute(&mut client, &root, "/sync", plan,
+            |client, jobs| jobs.into_iter().map(|job| {
+                let result = job.run(client);
+                JobResult { job, bytes: 0, duration: Duration::from_secs(0), result }
+            }).collect(),
+            |path, _| reported.push(path.to_string())).unwrap();
+        let remaining: Vec<bool> = ["p/f", "p/late", "q/x", "q/y", "t/kept"].iter()
+            .map(|path| root.join(path).exists())
+            .collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        // Unrecorded file keeps its directory, failed and planned children skip the removal
+        assert_eq!(reported, vec!["p", "t/missing"]);
+        assert_eq!(remaining, vec![false, true, true, true, true]);
+        assert_eq!((summary.downloaded, summary.actions, summary.failed), (1, 1, 2));
+    }
+}
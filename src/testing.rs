@@ -0,0 +1,337 @@
+//! FTP server on loopback interface used by tests and examples of this crate.
+//!
+//! Files are kept in memory. Any user is accepted unless the password is `wrong`. Only the
+//! commands issued by `FtpClient` are implemented, in both passive and active mode.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+
+/// Modification time of every file, as sent in MLSD and MDTM replies.
+const MODIFIED: &str = "20240102030405";
+
+#[derive(Default)]
+struct Tree {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+}
+
+impl Tree {
+
+    fn add_dir(&mut self, path: &str) {
+        let mut path = path.to_string();
+        while self.dirs.insert(path.clone()) && path != "/" {
+            path = parent(&path).to_string();
+        }
+    }
+
+    fn add_file(&mut self, path: &str, content: Vec<u8>) {
+        self.add_dir(parent(path));
+        self.files.insert(path.to_string(), content);
+    }
+
+    /// Names of files and directories directly in `dir`.
+    fn children(&self, dir: &str) -> (Vec<(&str, usize)>, Vec<&str>) {
+        let files = self.files.iter()
+            .filter(|&(path, _)| path != dir && parent(path) == dir)
+            .map(|(path, content)| (name(path), content.len()))
+            .collect();
+        let dirs = self.dirs.iter()
+            .filter(|path| path.as_str() != dir && parent(path) == dir)
+            .map(|path| name(path))
+            .collect();
+        (files, dirs)
+    }
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(pos) => &path[..pos]
+    }
+}
+
+fn name(path: &str) -> &str {
+    &path[path.rfind('/').map_or(0, |pos| pos + 1)..]
+}
+
+/// Resolve `path` given by client against current directory `cwd`.
+fn resolve(cwd: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = if path.starts_with('/') { Vec::new() } else { cwd.split('/').filter(|part| !part.is_empty()).collect() };
+    for part in path.split('/') {
+        match part {
+            "" | "." => { }
+            ".." => { parts.pop(); }
+            part => parts.push(part)
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+
+/// Running server. It stops accepting connections when dropped.
+pub struct TestServer {
+    address: String,
+    tree: Arc<Mutex<Tree>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TestServer {
+
+    /// Start server on random port with `files` given as path and content.
+    /// Parent directories of the files are created too.
+    pub fn start(files: &[(&str, &str)]) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind test server");
+        let address = listener.local_addr().expect("address of test server").to_string();
+
+        let mut tree = Tree::default();
+        tree.add_dir("/");
+        for &(path, content) in files {
+            tree.add_file(&resolve("/", path), content.as_bytes().to_vec());
+        }
+        let tree = Arc::new(Mutex::new(tree));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (shared, stop) = (tree.clone(), stopped.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let tree = shared.clone();
+                    thread::spawn(move || Session::new(stream, tree).run());
+                }
+            }
+        });
+
+        TestServer { address, tree, stopped }
+    }
+
+    /// Address of the server as `host:port`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Content of file at absolute `path`.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.tree.lock().unwrap().files.get(path).cloned()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the thread waiting for connections
+        let _ = TcpStream::connect(&self.address);
+    }
+}
+
+
+struct Session {
+    control: BufReader<TcpStream>,
+    tree: Arc<Mutex<Tree>>,
+    cwd: String,
+    passive: Option<TcpListener>,
+    active: Option<SocketAddrV4>,
+    offset: usize,
+}
+
+impl Session {
+
+    fn new(stream: TcpStream, tree: Arc<Mutex<Tree>>) -> Session {
+        Session {
+            control: BufReader::new(stream),
+            tree,
+            cwd: "/".to_string(),
+            passive: None,
+            active: None,
+            offset: 0,
+        }
+    }
+
+    fn tree(&self) -> MutexGuard<'_, Tree> {
+        self.tree.lock().unwrap()
+    }
+
+    fn reply(&mut self, code: u32, text: &str) -> io::Result<()> {
+        write!(self.control.get_mut(), "{} {}\r\n", code, text)
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        self.reply(220, "Test server ready")?;
+        loop {
+            let mut line = String::new();
+            if self.control.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let line = line.trim_end();
+            let (command, arg) = match line.find(' ') {
+                Some(pos) => (line[..pos].to_uppercase(), &line[pos+1..]),
+                None => (line.to_uppercase(), "")
+            };
+            let path = resolve(&self.cwd, arg);
+
+            match command.as_str() {
+                "USER" => self.reply(331, "Password required")?,
+                "PASS" if arg == "wrong" => self.reply(530, "Login incorrect")?,
+                "PASS" | "ACCT" => self.reply(230, "Logged in")?,
+                "TYPE" => self.reply(200, "Type set")?,
+                "PWD" => {
+                    let text = format!("\"{}\" is the current directory", self.cwd.replace('"', "\"\""));
+                    self.reply(257, &text)?;
+                }
+                "CWD" => if self.tree().dirs.contains(&path) {
+                    self.cwd = path;
+                    self.reply(250, "Directory changed")?;
+                } else {
+                    self.reply(550, "No such directory")?;
+                },
+                "MKD" => {
+                    let created = {
+                        let mut tree = self.tree();
+                        let exists = tree.dirs.contains(&path) || tree.files.contains_key(&path);
+                        if !exists {
+                            tree.add_dir(&path);
+                        }
+                        !exists
+                    };
+                    if created {
+                        self.reply(257, &format!("\"{}\" created", path))?;
+                    } else {
+                        self.reply(550, "File exists")?;
+                    }
+                }
+                "RMD" => {
+                    let removed = {
+                        let mut tree = self.tree();
+                        let empty = tree.children(&path) == (vec![], vec![]);
+                        path != "/" && empty && tree.dirs.remove(&path)
+                    };
+                    if removed {
+                        self.reply(250, "Directory removed")?;
+                    } else {
+                        self.reply(550, "Can't remove directory")?;
+                    }
+                }
+                "DELE" => if self.tree().files.remove(&path).is_some() {
+                    self.reply(250, "File deleted")?;
+                } else {
+                    self.reply(550, "No such file")?;
+                },
+                "SIZE" => {
+                    let size = self.tree().files.get(&path).map(|content| content.len());
+                    match size {
+                        Some(size) => self.reply(213, &size.to_string())?,
+                        None => self.reply(550, "No such file")?
+                    }
+                }
+                "MDTM" => if self.tree().files.contains_key(&path) {
+                    self.reply(213, MODIFIED)?;
+                } else {
+                    self.reply(550, "No such file")?;
+                },
+                "REST" => match arg.parse() {
+                    Ok(offset) => {
+                        self.offset = offset;
+                        self.reply(350, "Restarting")?;
+                    }
+                    Err(_) => self.reply(501, "Invalid offset")?
+                },
+                "PASV" => {
+                    let listener = TcpListener::bind("127.0.0.1:0")?;
+                    let port = listener.local_addr()?.port();
+                    self.passive = Some(listener);
+                    self.reply(227, &format!("Entering Passive Mode (127,0,0,1,{},{})", port >> 8, port & 0xff))?;
+                }
+                "PORT" => {
+                    let numbers: Vec<u16> = arg.split(',').filter_map(|n| n.parse().ok()).collect();
+                    if numbers.len() == 6 {
+                        let addr = format!("{}.{}.{}.{}:{}", numbers[0], numbers[1], numbers[2], numbers[3], numbers[4] << 8 | numbers[5]);
+                        self.active = addr.parse().ok();
+                        self.reply(200, "PORT command successful")?;
+                    } else {
+                        self.reply(501, "Invalid address")?;
+                    }
+                }
+                "MLSD" | "LIST" | "NLST" => {
+                    let listing = {
+                        let tree = self.tree();
+                        if !tree.dirs.contains(&path) {
+                            None
+                        } else {
+                            let (files, dirs) = tree.children(&path);
+                            let mut listing = String::new();
+                            for name in dirs {
+                                listing.push_str(&match command.as_str() {
+                                    "MLSD" => format!("type=dir;modify={}; {}\r\n", MODIFIED, name),
+                                    "LIST" => format!("drwxr-xr-x 2 ftp ftp 0 Jan 02 2024 {}\r\n", name),
+                                    _ => format!("{}\r\n", name)
+                                });
+                            }
+                            for (name, size) in files {
+                                listing.push_str(&match command.as_str() {
+                                    "MLSD" => format!("type=file;size={};modify={}; {}\r\n", size, MODIFIED, name),
+                                    "LIST" => format!("-rw-r--r-- 1 ftp ftp {} Jan 02 2024 {}\r\n", size, name),
+                                    _ => format!("{}\r\n", name)
+                                });
+                            }
+                            Some(listing)
+                        }
+                    };
+                    match listing {
+                        Some(listing) => self.send(listing.as_bytes())?,
+                        None => self.reply(550, "No such directory")?
+                    }
+                }
+                "RETR" => {
+                    let content = self.tree().files.get(&path).cloned();
+                    let offset = ::std::mem::replace(&mut self.offset, 0);
+                    match content {
+                        Some(content) => self.send(&content[::std::cmp::min(offset, content.len())..])?,
+                        None => self.reply(550, "No such file")?
+                    }
+                }
+                "STOR" => {
+                    self.reply(150, "Opening data connection")?;
+                    let mut content = Vec::new();
+                    match self.open_data().and_then(|mut stream| stream.read_to_end(&mut content)) {
+                        Ok(_) => {
+                            self.tree().add_file(&path, content);
+                            self.reply(226, "Transfer complete")?;
+                        }
+                        Err(_) => self.reply(425, "Can't open data connection")?
+                    }
+                }
+                "ABOR" => self.reply(226, "Abort successful")?,
+                "NOOP" => self.reply(200, "OK")?,
+                "QUIT" => return self.reply(221, "Goodbye"),
+                _ => self.reply(502, "Command not implemented")?
+            }
+        }
+    }
+
+    fn open_data(&mut self) -> io::Result<TcpStream> {
+        if let Some(listener) = self.passive.take() {
+            return listener.accept().map(|(stream, _)| stream);
+        }
+        match self.active.take() {
+            Some(addr) => TcpStream::connect(SocketAddr::V4(addr)),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "Use PASV or PORT first"))
+        }
+    }
+
+    /// Send `data` over data connection. Client may close it early to abort the transfer.
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.reply(150, "Opening data connection")?;
+        let sent = self.open_data().and_then(|mut stream| stream.write_all(data));
+        match sent {
+            Ok(()) => self.reply(226, "Transfer complete"),
+            Err(_) => self.reply(426, "Transfer aborted")
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Log of FTP dialogue for debugging interoperability with servers.
+//!
+//! Every line starts with UTC time and number of the session. Commands sent are marked `>`,
+//! replies received `<` and events of control and data connections `*`.
+//! Passwords and accounts are never written.
+
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::FtpError;
+
+
+/// Trace of single session. Sessions created by `new_session` write to the same output.
+#[derive(Clone)]
+pub struct Trace {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    sessions: Arc<AtomicUsize>,
+    session: usize,
+}
+
+impl Trace {
+
+    pub fn new(out: Box<dyn Write + Send>) -> Trace {
+        Trace {
+            out: Arc::new(Mutex::new(out)),
+            sessions: Arc::new(AtomicUsize::new(1)),
+            session: 1,
+        }
+    }
+
+    pub fn stderr() -> Trace {
+        Trace::new(Box::new(::std::io::stderr()))
+    }
+
+    /// Trace written to file, existing file is overwritten.
+    pub fn file(path: &Path) -> Result<Trace, FtpError> {
+        let file = File::create(path)?;
+        Ok(Trace::new(Box::new(file)))
+    }
+
+    /// Trace of another session, e.g. of connection pool, with its own number.
+    pub fn new_session(&self) -> Trace {
+        Trace {
+            out: self.out.clone(),
+            sessions: self.sessions.clone(),
+            session: self.sessions.fetch_add(1, Ordering::SeqCst) + 1,
+        }
+    }
+
+    /// Command sent to server.
+    pub fn command(&self, line: &str) {
+        self.write('>', &redact(line));
+    }
+
+    /// Reply line received from server.
+    pub fn reply(&self, line: &str) {
+        self.write('<', line.trim_end());
+    }
+
+    pub fn event(&self, text: &str) {
+        self.write('*', text);
+    }
+
+    fn write(&self, mark: char, text: &str) {
+        let mut out = match self.out.lock() {
+            Ok(out) => out,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        // Failing trace must not break the transfer
+        let _ = writeln!(out, "{} [{}] {} {}", timestamp(), self.session, mark, text);
+        let _ = out.flush();
+    }
+}
+
+impl Debug for Trace {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "Trace {{ session: {} }}", self.session)
+    }
+}
+
+
+/// Replace argument of commands carrying secrets.
+fn redact(line: &str) -> String {
+    let command = line.split(' ').next().unwrap_or("");
+    match command.to_ascii_uppercase().as_str() {
+        "PASS" | "ACCT" if line.len() > command.len() => format!("{} ****", command),
+        _ => line.to_string()
+    }
+}
+
+/// Current UTC time of day with milliseconds.
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs() % 86400;
+    format!("{:02}:{:02}:{:02}.{:03}", secs / 3600, secs / 60 % 60, secs % 60, now.subsec_millis())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ftp_client::FtpClient;
+    use crate::testing::TestServer;
+
+    /// Output shared with the test, trace owns its own handle.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn redacts_secrets() {
+        let cases = [
+            ("PASS secret", "PASS ****"),
+            ("pass secret", "pass ****"),
+            ("Pass two words", "Pass ****"),
+            ("ACCT billing", "ACCT ****"),
+            ("acct billing", "acct ****"),
+            ("PASS ", "PASS ****"),
+            ("PASS", "PASS"),
+            ("USER anonymous", "USER anonymous"),
+            ("RETR PASS secret", "RETR PASS secret"),
+            ("PASSIVE secret", "PASSIVE secret"),
+            ("", ""),
+        ];
+        for &(line, expected) in cases.iter() {
+            assert_eq!(redact(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn trace_of_login_hides_password() {
+        let server = TestServer::start(&[]);
+        let buffer = Buffer::default();
+        let trace = Trace::new(Box::new(buffer.clone()));
+        let mut client = FtpClient::connect(server.address(), None, Some(trace)).unwrap();
+        assert!(client.login("user", "s3cr3t", None).unwrap());
+        client.quit();
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(text.contains("> USER user"), "{}", text);
+        assert!(text.contains("> PASS ****"), "{}", text);
+        assert!(!text.contains("s3cr3t"), "{}", text);
+    }
+}
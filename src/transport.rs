@@ -0,0 +1,203 @@
+//! Connections used by `FtpClient` for control and data channels.
+//!
+//! `TcpTransport` connects directly to the server. Other transports can carry the channels over
+//! TLS, a proxy, Unix socket, stdio of a child process or in-memory buffers.
+//!
+//! ```
+//! use std::io::{self, Cursor};
+//! use std::net::SocketAddrV4;
+//! use std::sync::Arc;
+//! use ftp_cli::FtpClient;
+//! use ftp_cli::transport::{Channel, Duplex, Transport};
+//!
+//! /// Plays back replies of the server and discards commands.
+//! #[derive(Debug)]
+//! struct Replay(&'static str);
+//!
+//! impl Transport for Replay {
+//!     fn connect(&self, _server: &str) -> io::Result<Box<dyn Channel>> {
+//!         Ok(Box::new(Duplex::new(Cursor::new(self.0.as_bytes()), io::sink())))
+//!     }
+//!
+//!     fn connect_data(&self, _addr: SocketAddrV4) -> io::Result<Box<dyn Channel>> {
+//!         Err(io::Error::new(io::ErrorKind::Unsupported, "no data connections"))
+//!     }
+//! }
+//!
+//! let replies = "220 Welcome\r\n331 Password\r\n230 Logged in\r\n257 \"/home\" is the current directory\r\n";
+//! let mut client = FtpClient::connect_transport("example.com:21", Arc::new(Replay(replies)), None).unwrap();
+//! assert!(client.login("user", "secret", None).unwrap());
+//! assert_eq!(client.pwd().unwrap(), "/home");
+//! ```
+
+use std::fmt::{Debug, Formatter};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, SocketAddrV4, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+
+/// Bidirectional byte stream carrying control or data channel.
+pub trait Channel: Read + Write + Send {
+
+    /// Address of the other end when the channel is a network connection.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl Channel for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+#[cfg(unix)]
+impl Channel for ::std::os::unix::net::UnixStream {}
+
+impl<R: Read + Send, W: Write + Send> Channel for Duplex<R, W> {}
+
+/// Opens channels to the server. Shared by all sessions of connection pool.
+pub trait Transport: Send + Sync {
+
+    /// Open control channel to `server` given as `host:port`.
+    fn connect(&self, server: &str) -> io::Result<Box<dyn Channel>>;
+
+    /// Open data channel to address given by server in reply to PASV.
+    fn connect_data(&self, addr: SocketAddrV4) -> io::Result<Box<dyn Channel>>;
+
+    /// Start listening for data channel opened by server in active mode.
+    fn listen(&self, _addr: SocketAddrV4) -> io::Result<Box<dyn DataListener>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Active mode is not supported by transport"))
+    }
+
+    /// Timeout of network operations, if the transport has any.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Debug for dyn Transport {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "Transport")
+    }
+}
+
+/// Waits for data channel in active mode.
+pub trait DataListener {
+    /// Address sent to server in PORT command.
+    fn local_addr(&self) -> io::Result<SocketAddrV4>;
+
+    fn accept(&mut self) -> io::Result<Box<dyn Channel>>;
+}
+
+
+/// Plain TCP connections with optional timeout of all network operations.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TcpTransport {
+    pub timeout: Option<Duration>,
+}
+
+impl TcpTransport {
+
+    pub fn new(timeout: Option<Duration>) -> TcpTransport {
+        TcpTransport { timeout }
+    }
+}
+
+impl Transport for TcpTransport {
+
+    fn connect(&self, server: &str) -> io::Result<Box<dyn Channel>> {
+        Ok(Box::new(open_stream(server, self.timeout)?))
+    }
+
+    fn connect_data(&self, addr: SocketAddrV4) -> io::Result<Box<dyn Channel>> {
+        Ok(Box::new(open_stream(addr, self.timeout)?))
+    }
+
+    fn listen(&self, addr: SocketAddrV4) -> io::Result<Box<dyn DataListener>> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Box::new(TcpDataListener { listener, timeout: self.timeout }))
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+struct TcpDataListener {
+    listener: TcpListener,
+    timeout: Option<Duration>,
+}
+
+impl DataListener for TcpDataListener {
+
+    fn local_addr(&self) -> io::Result<SocketAddrV4> {
+        match self.listener.local_addr()? {
+            SocketAddr::V4(addr) => Ok(addr),
+            SocketAddr::V6(addr) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("PORT needs IPv4 address, listening on {}", addr)))
+        }
+    }
+
+    fn accept(&mut self) -> io::Result<Box<dyn Channel>> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Connect to first reachable address of `server` with optional timeout of all operations.
+fn open_stream<A: ToSocketAddrs>(server: A, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    // Failed name lookup has no specific kind, it must not look like error of local file
+    let addrs = server.to_socket_addrs().map_err(|e| io::Error::new(io::ErrorKind::HostUnreachable, e))?;
+    let mut last_error = None;
+    for addr in addrs {
+        let connected = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr)
+        };
+        match connected {
+            Ok(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+                return Ok(stream);
+            }
+            Err(err) => last_error = Some(err)
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::HostUnreachable, "no address to connect to")))
+}
+
+
+/// Channel made of separate halves, e.g. stdout and stdin of child process or pair of pipes.
+pub struct Duplex<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Duplex<R, W> {
+
+    pub fn new(reader: R, writer: W) -> Duplex<R, W> {
+        Duplex { reader, writer }
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R: Read, W: Write> Read for Duplex<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R: Read, W: Write> Write for Duplex<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::FtpError;
+use crate::glob;
+use crate::ftp_client::FtpClient;
+use crate::listing::{is_safe_name, EntryKind, RemoteEntry};
+use crate::pool::TransferJob;
+
+
+/// Content of remote directory tree. All paths are relative to the tree root and use `/` separator.
+#[derive(Debug, Default)]
+pub struct RemoteTree {
+    pub dirs: Vec<String>,
+    pub files: Vec<(String, RemoteEntry)>,
+    /// Entries which were not followed (unsafe names, links, special files).
+    pub skipped: Vec<String>,
+}
+
+/// Walk remote directory `root` recursively.
+pub fn walk_remote(client: &mut FtpClient, root: &str) -> Result<RemoteTree, FtpError> {
+    let mut tree = RemoteTree::default();
+    let mut pending = vec![String::new()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = client.list_entries(&join_remote(root, &dir))?;
+        for entry in entries {
+            let relative = join_remote(&dir, &entry.name);
+            if !is_safe_name(&entry.name) {
+                tree.skipped.push(relative);
+                continue;
+            }
+            match entry.kind {
+                EntryKind::Directory => {
+                    tree.dirs.push(relative.clone());
+                    pending.push(relative);
+                }
+                EntryKind::File => tree.files.push((relative, entry)),
+                EntryKind::Link | EntryKind::Other => tree.skipped.push(relative)
+            }
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Prepare download of remote `path` into `local_root`.
+/// Directory trees are recreated locally and a job is returned for every file in them.
+/// Entries which could escape `local_root` are skipped and returned by name.
+pub fn prepare_download(client: &mut FtpClient, path: &str, local_root: &Path) -> Result<(Vec<TransferJob>, Vec<String>), FtpError> {
+    // SIZE succeeds only for regular files
+    if client.size(path).is_ok() {
+        let name = file_name(path);
+        if name.is_empty() {
+            return Err(FtpError::OperationFailed(format!("Invalid file name \"{}\".", path)));
+        }
+        let local = local_root.join(name);
+        return Ok((vec![TransferJob::download(path, &local.to_string_lossy())], Vec::new()));
+    }
+
+    let tree = walk_remote(client, path)?;
+    let target = match file_name(path) {
+        "" => local_root.to_path_buf(),
+        name => local_root.join(name)
+    };
+
+    fs::create_dir_all(&target)?;
+    for dir in &tree.dirs {
+        fs::create_dir_all(target.join(dir))?;
+    }
+
+    let jobs = tree.files.iter().map(|(relative, _)| {
+        let local = target.join(relative);
+        TransferJob::download(&join_remote(path, relative), &local.to_string_lossy())
+    }).collect();
+
+    Ok((jobs, tree.skipped))
+}
+
+/// Plan of recursive upload.
+#[derive(Debug, Default)]
+pub struct UploadPlan {
+    pub jobs: Vec<TransferJob>,
+    /// Remote directories created while preparing the upload.
+    pub created_dirs: Vec<String>,
+    /// Local entries which are not uploaded (links, special files, link loops).
+    pub skipped: Vec<String>,
+}
+
+/// Prepare upload of local `path` into remote directory `remote_root`.
+/// Missing remote directories are created and a job is returned for every file.
+/// Symbolic links are followed only when `follow_links` is set.
+pub fn prepare_upload(client: &mut FtpClient, path: &Path, remote_root: &str, follow_links: bool) -> Result<UploadPlan, FtpError> {
+    let mut plan = UploadPlan::default();
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => String::new()
+    };
+
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_dir() {
+        let remote = join_remote(remote_root, &name);
+        plan.jobs.push(TransferJob::upload(&path.to_string_lossy(), &remote));
+        return Ok(plan);
+    }
+
+    let cwd = client.pwd()?;
+    let target = join_remote(remote_root, &name);
+    let mut visited = Vec::new();
+    let mut pending = vec![(path.to_path_buf(), target)];
+
+    while let Some((dir, remote_dir)) = pending.pop() {
+        // Following links may lead back to directory we've already seen
+        if let Ok(canonical) = fs::canonicalize(&dir) {
+            if visited.contains(&canonical) {
+                plan.skipped.push(dir.to_string_lossy().into_owned());
+                continue;
+            }
+            visited.push(canonical);
+        }
+
+        if !remote_dir.is_empty() && ensure_remote_dir(client, &remote_dir, &cwd)? {
+            plan.created_dirs.push(remote_dir.clone());
+        }
+
+        let mut entries = fs::read_dir(&dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let local = entry.path();
+            let remote = join_remote(&remote_dir, &entry.file_name().to_string_lossy());
+            let file_type = entry.file_type()?;
+            let metadata = match file_type.is_symlink() {
+                true if !follow_links => {
+                    plan.skipped.push(local.to_string_lossy().into_owned());
+                    continue;
+                }
+                true => match fs::metadata(&local) {
+                    Ok(metadata) => metadata,
+                    // Broken link
+                    Err(_) => {
+                        plan.skipped.push(local.to_string_lossy().into_owned());
+                        continue;
+                    }
+                },
+                false => entry.metadata()?
+            };
+
+            if metadata.is_dir() {
+                pending.push((local, remote));
+            }
+            else if metadata.is_file() {
+                plan.jobs.push(TransferJob::upload(&local.to_string_lossy(), &remote));
+            }
+            else {
+                plan.skipped.push(local.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Create remote directory unless it already exists. Returns `true` when it was created.
+fn ensure_remote_dir(client: &mut FtpClient, path: &str, cwd: &str) -> Result<bool, FtpError> {
+    match client.mkdir(path) {
+        Ok(()) => Ok(true),
+        Err(FtpError::IoError(err)) => Err(FtpError::IoError(err)),
+        Err(err) => {
+            // Servers report existing directory as generic failure, so check it's really there
+            match client.cd(path) {
+                Ok(()) => {
+                    client.cd(cwd)?;
+                    Ok(false)
+                }
+                Err(_) => Err(err)
+            }
+        }
+    }
+}
+
+/// Expand wildcard pattern against remote listings.
+/// Returns matching paths and whether they are directories.
+pub fn expand_remote(client: &mut FtpClient, pattern: &str) -> Result<Vec<(String, bool)>, FtpError> {
+    glob::expand(pattern, |dir| {
+        let entries = client.list_entries(dir)?;
+        Ok(entries.into_iter()
+            .filter(|e| is_safe_name(&e.name))
+            .map(|e| (e.name, e.kind == EntryKind::Directory))
+            .collect())
+    })
+}
+
+/// Expand wildcard pattern against local filesystem. Symbolic links are not directories.
+/// Returns matching paths and whether they are directories.
+pub fn expand_local(pattern: &str) -> Result<Vec<(String, bool)>, FtpError> {
+    glob::expand(pattern, |dir| {
+        let dir = match dir {
+            "" => ".",
+            _ => dir
+        };
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            // Links to directories aren't followed, like `put -r` without -L, so they can't make `**` loop
+            let is_dir = fs::symlink_metadata(entry.path()).map(|m| m.is_dir()).unwrap_or(false);
+            names.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+        }
+        Ok(names)
+    })
+}
+
+/// Join remote paths with `/`.
+pub fn join_remote(base: &str, name: &str) -> String {
+    match (base, name) {
+        ("", _) => name.to_string(),
+        (_, "") => base.to_string(),
+        _ if base.ends_with('/') => format!("{}{}", base, name),
+        _ => format!("{}/{}", base, name)
+    }
+}
+
+/// Last component of the path which is safe to use as local file name
+/// (empty for root, current or parent directory).
+pub fn file_name(path: &str) -> &str {
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+    match is_safe_name(name) {
+        true => name,
+        false => ""
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_remote_paths() {
+        let cases = [
+            ("", "a", "a"),
+            ("dir", "", "dir"),
+            ("dir", "a", "dir/a"),
+            ("dir/", "a", "dir/a"),
+            ("/", "a", "/a"),
+        ];
+        for &(base, name, path) in cases.iter() {
+            assert_eq!(join_remote(base, name), path);
+        }
+    }
+
+    #[test]
+    fn takes_safe_file_name() {
+        let cases = [("dir/a.txt", "a.txt"), ("dir/sub/", "sub"), ("a", "a"), ("/", ""), ("dir/..", ""), (".", "")];
+        for &(path, name) in cases.iter() {
+            assert_eq!(file_name(path), name, "{}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_expansion_doesnt_follow_directory_links() {
+        let root = ::std::env::temp_dir().join(format!("ftp-cli-glob-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("data/sub")).unwrap();
+        fs::write(root.join("data/a.csv"), "").unwrap();
+        fs::write(root.join("data/sub/b.csv"), "").unwrap();
+        // Link back to an ancestor would make `**` recurse forever
+        ::std::os::unix::fs::symlink(&root, root.join("data/sub/loop")).unwrap();
+
+        let pattern = format!("{}/**/*.csv", root.display());
+        let found = expand_local(&pattern);
+        fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<_> = found.unwrap().into_iter()
+            .map(|(path, _)| path[root.to_string_lossy().len()..].to_string())
+            .collect();
+        assert_eq!(names, vec!["/data/a.csv", "/data/sub/b.csv"]);
+    }
+}
@@ -0,0 +1,291 @@
+//! Passwords stored in local file encrypted by master passphrase.
+//!
+//! The file starts with magic bytes followed by random salt and nonce, the rest is list of
+//! credentials encrypted by ChaCha20-Poly1305. The key is derived from the passphrase by Argon2id.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+
+use ftp_cli::error::FtpError;
+
+const MAGIC: &[u8] = b"FTPCLI-VAULT1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+
+/// Password of user on given host.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credential {
+    pub host: String,
+    pub user: String,
+    pub password: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Vault {
+    #[serde(default)]
+    credentials: Vec<Credential>,
+}
+
+impl Vault {
+
+    /// Location given by `FTP_CLI_VAULT` variable or `ftp-cli/credentials` in user's data directory.
+    pub fn path() -> Option<PathBuf> {
+        match ::std::env::var_os("FTP_CLI_VAULT") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::data_dir().map(|dir| dir.join("ftp-cli").join("credentials"))
+        }
+    }
+
+    /// Read and decrypt the file.
+    pub fn load(path: &Path, passphrase: &str) -> Result<Vault, FtpError> {
+        let data = fs::read(path)?;
+        let header = MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if data.len() < header || !data.starts_with(MAGIC) {
+            return Err(FtpError::OperationFailed(format!("{} is not a credential store.", path.display())));
+        }
+        let salt = &data[MAGIC.len()..MAGIC.len() + SALT_LEN];
+        let nonce = Nonce::from_slice(&data[MAGIC.len() + SALT_LEN..header]);
+
+        let cipher = cipher(passphrase, salt)?;
+        let text = cipher.decrypt(nonce, &data[header..])
+            .map_err(|_| FtpError::OperationFailed("Wrong passphrase or damaged credential store.".to_string()))?;
+        let text = String::from_utf8(text)?;
+        toml::from_str(&text).map_err(|e| FtpError::OperationFailed(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Encrypt and write the file readable by owner only. New salt and nonce are used every time.
+    pub fn save(&self, path: &Path, passphrase: &str) -> Result<(), FtpError> {
+        let text = toml::to_string(self).map_err(|e| FtpError::OperationFailed(e.to_string()))?;
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        random(&mut salt)?;
+        random(&mut nonce)?;
+
+        let cipher = cipher(passphrase, &salt)?;
+        let encrypted = cipher.encrypt(Nonce::from_slice(&nonce), text.as_bytes())
+            .map_err(|_| FtpError::OperationFailed("Encryption failed.".to_string()))?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written aside and renamed so interrupted write never destroys stored passwords
+        let temp = path.with_extension("tmp");
+        {
+            let mut file = create_private(&temp)?;
+            file.write_all(MAGIC)?;
+            file.write_all(&salt)?;
+            file.write_all(&nonce)?;
+            file.write_all(&encrypted)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    pub fn credentials(&self) -> &[Credential] {
+        &self.credentials
+    }
+
+    /// Password of user on host. Host names are case insensitive.
+    pub fn find(&self, host: &str, user: &str) -> Option<&str> {
+        self.credentials.iter()
+            .find(|c| c.host.eq_ignore_ascii_case(host) && c.user == user)
+            .map(|c| c.password.as_str())
+    }
+
+    /// Add credential, replacing password of the same host and user.
+    pub fn add(&mut self, credential: Credential) {
+        self.remove(&credential.host, &credential.user);
+        self.credentials.push(credential);
+        self.credentials.sort_by(|a, b| (&a.host, &a.user).cmp(&(&b.host, &b.user)));
+    }
+
+    /// Remove credential and return whether it was there.
+    pub fn remove(&mut self, host: &str, user: &str) -> bool {
+        let count = self.credentials.len();
+        self.credentials.retain(|c| !(c.host.eq_ignore_ascii_case(host) && c.user == user));
+        self.credentials.len() != count
+    }
+}
+
+
+/// Run shell command and take the first line of its output as password.
+pub fn password_from_command(command: &str) -> Result<String, FtpError> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).stdin(Stdio::inherit()).stderr(Stdio::inherit()).output()
+    }
+    else {
+        Command::new("sh").arg("-c").arg(command).stdin(Stdio::inherit()).stderr(Stdio::inherit()).output()
+    };
+    let output = output?;
+    if !output.status.success() {
+        return Err(FtpError::OperationFailed(format!("Password command \"{}\" failed ({}).", command, output.status)));
+    }
+    let text = String::from_utf8(output.stdout)?;
+    Ok(text.lines().next().unwrap_or("").to_string())
+}
+
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, FtpError> {
+    let mut key = [0u8; 32];
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| FtpError::OperationFailed(format!("Key derivation failed: {}", e)))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn random(buf: &mut [u8]) -> Result<(), FtpError> {
+    getrandom::getrandom(buf).map_err(|e| FtpError::OperationFailed(format!("No random numbers: {}", e)))
+}
+
+/// Create new file readable by owner only. Leftover of interrupted save is removed first,
+/// permissions are set only when the file is created.
+fn create_private(path: &Path) -> Result<fs::File, FtpError> {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != ErrorKind::NotFound {
+            return Err(FtpError::IoError(e));
+        }
+    }
+    Ok(private_options().open(path)?)
+}
+
+#[cfg(unix)]
+fn private_options() -> fs::OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true).mode(0o600);
+    options
+}
+
+#[cfg(not(unix))]
+fn private_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    options
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!("ftp-cli-vault-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn credential(host: &str, user: &str, password: &str) -> Credential {
+        Credential { host: host.to_string(), user: user.to_string(), password: password.to_string() }
+    }
+
+    #[test]
+    fn adds_finds_and_removes_credentials() {
+        let mut vault = Vault::default();
+        vault.add(credential("ftp.example.com", "deploy", "one"));
+        vault.add(credential("ftp.example.com", "backup", "two"));
+        vault.add(credential("FTP.example.com", "deploy", "three"));
+
+        assert_eq!(vault.credentials().len(), 2);
+        assert_eq!(vault.find("ftp.example.com", "deploy"), Some("three"));
+        assert_eq!(vault.find("Ftp.Example.Com", "backup"), Some("two"));
+        assert_eq!(vault.find("ftp.example.com", "Deploy"), None);
+        assert_eq!(vault.find("other.example.com", "deploy"), None);
+
+        assert!(vault.remove("ftp.EXAMPLE.com", "deploy"));
+        assert!(!vault.remove("ftp.example.com", "deploy"));
+        assert_eq!(vault.find("ftp.example.com", "deploy"), None);
+        assert_eq!(vault.find("ftp.example.com", "backup"), Some("two"));
+    }
+
+    #[test]
+    fn loads_saved_vault_only_with_its_passphrase() {
+        let dir = temp_dir("roundtrip");
+        let path = dir.join("credentials");
+        let mut vault = Vault::default();
+        vault.add(credential("ftp.example.com", "deploy", "p@ss \"word\""));
+        vault.save(&path, "passphrase").unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let loaded = Vault::load(&path, "passphrase");
+        let wrong = Vault::load(&path, "Passphrase");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(data.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&data).contains("p@ss"));
+        assert_eq!(loaded.unwrap().find("ftp.example.com", "deploy"), Some("p@ss \"word\""));
+        assert!(wrong.is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_and_foreign_files() {
+        let dir = temp_dir("tampered");
+        let path = dir.join("credentials");
+        let mut vault = Vault::default();
+        vault.add(credential("ftp.example.com", "deploy", "secret"));
+        vault.save(&path, "passphrase").unwrap();
+        let data = fs::read(&path).unwrap();
+
+        let mut results = Vec::new();
+        // Salt, nonce and encrypted text are all authenticated
+        for &pos in [MAGIC.len(), MAGIC.len() + SALT_LEN, data.len() - 1].iter() {
+            let mut tampered = data.clone();
+            tampered[pos] ^= 1;
+            fs::write(&path, &tampered).unwrap();
+            results.push(Vault::load(&path, "passphrase").is_err());
+        }
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        results.push(Vault::load(&path, "passphrase").is_err());
+        fs::write(&path, "[[credentials]]\nhost = \"a\"\n").unwrap();
+        results.push(Vault::load(&path, "passphrase").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results, vec![true; 5]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_private_file_without_reusing_existing_one() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = temp_dir("private");
+        let path = dir.join("credentials.tmp");
+        let target = dir.join("target");
+
+        fs::write(&path, "leftover").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        create_private(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let content = fs::read(&path).unwrap();
+
+        let refused = private_options().open(&path).map_err(|e| e.kind());
+
+        // Planted link is replaced, its target stays untouched
+        fs::remove_file(&path).unwrap();
+        fs::write(&target, "keep").unwrap();
+        symlink(&target, &path).unwrap();
+        create_private(&path).unwrap().write_all(b"new").unwrap();
+        let target_content = fs::read_to_string(&target).unwrap();
+        let is_link = fs::symlink_metadata(&path).unwrap().file_type().is_symlink();
+
+        let mut vault = Vault::default();
+        vault.add(credential("host", "user", "secret"));
+        vault.save(&dir.join("credentials"), "passphrase").unwrap();
+        let saved_mode = fs::metadata(dir.join("credentials")).unwrap().permissions().mode() & 0o777;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mode, 0o600);
+        assert!(content.is_empty());
+        assert_eq!(refused.err(), Some(ErrorKind::AlreadyExists));
+        assert_eq!(target_content, "keep");
+        assert!(!is_link);
+        assert_eq!(saved_mode, 0o600);
+    }
+}